@@ -0,0 +1,936 @@
+//! The StackCell assembler, which turns mnemonics (`.casm` files) into `.cel`
+//! programs.
+
+use std::collections::{HashMap, HashSet};
+
+use logos::{Lexer, Logos};
+
+use crate::dialect::{Dialect, Extension};
+use crate::source_map::SourceMap;
+use ast::{Condition, Kind, Mnemonic, Operand, Span, Spanned, Statement, Target, Value};
+use diagnostic::Diagnostic;
+use source::Sources;
+
+pub mod ast;
+pub mod diagnostic;
+pub mod disasm;
+pub mod fmt;
+mod macros;
+pub mod source;
+
+/// Brackets are pushed by value, since `[`, `]`, `(` and `)` are matched up by
+/// scanning the program's bytes, and a literal one would be taken for a loop.
+fn parse_char_literal(lexer: &mut Lexer<Token>) -> String {
+    match lexer.slice().chars().nth(1).unwrap() {
+        bracket @ ('[' | ']' | '(' | ')') => format!("#{:02x}", bracket as u8),
+        c => format!("'{}", c),
+    }
+}
+
+/// Decimal literals which don't fit in a byte are invalid tokens.
+fn parse_decimal_literal(lexer: &mut Lexer<Token>) -> Option<String> {
+    let value = lexer.slice().trim_start_matches('#');
+    Some(format!("#{:02x}", value.parse::<u8>().ok()?))
+}
+
+fn parse_hex_literal(lexer: &mut Lexer<Token>) -> String {
+    lexer.slice().replace('$', "#")
+}
+
+fn parse_binary_literal(lexer: &mut Lexer<Token>) -> String {
+    let value = lexer.slice().trim_start_matches('b');
+    format!("#{:02x}", u8::from_str_radix(value, 2).unwrap())
+}
+
+#[derive(Logos, Debug, PartialEq, Clone)]
+enum Token {
+    #[token("add")]
+    Add,
+    #[token("sub")]
+    Subtract,
+    #[token("mul")]
+    Multiply,
+    #[token("div")]
+    Divide,
+    #[token("mod")]
+    Modulo,
+    #[token("neg")]
+    #[token("inv")]
+    BitwiseNegate,
+    #[token("not")]
+    LogicalNot,
+    #[token("and")]
+    BitwiseAnd,
+    #[token("or")]
+    BitwiseOr,
+    #[token("xor")]
+    BitwiseXor,
+    #[token("eq")]
+    Equal,
+    #[token("lt")]
+    Less,
+    #[token("gt")]
+    Greater,
+    #[token("while")]
+    #[token("loop")]
+    LoopStart,
+    #[token("nonzero")]
+    LoopConditionNotZero,
+    #[token("zero")]
+    LoopConditionZero,
+    #[token("cont")]
+    #[token("next")]
+    LoopContinue,
+    #[token("dup")]
+    Duplicate,
+    #[token("drop")]
+    Drop,
+    #[token("swap")]
+    Swap,
+    #[token("values")]
+    SwapValues,
+    #[token("stacks")]
+    SwapStacks,
+    #[token("jz")]
+    Conditional,
+    #[token("jmp")]
+    Jump,
+    #[regex("[1-9]", |lex| lex.slice().parse::<u8>().unwrap(), priority = 2)]
+    JumpDistance(u8),
+    /// A number other than a jump distance, such as a repeat count.
+    #[regex("[0-9]+", |lex| lex.slice().parse::<usize>().ok())]
+    Number(usize),
+    #[token("push")]
+    Push,
+    #[regex(r"#[0-9]+", parse_decimal_literal)]
+    #[regex(r"\$[0-9a-fA-F][0-9a-fA-F]", parse_hex_literal)]
+    #[regex(r"b[01][01][01][01][01][01][01][01]", parse_binary_literal)]
+    #[regex(r"'.'", parse_char_literal)]
+    Literal(String),
+    #[regex(r#""([^"\\]|\\[^\n])*""#, |v| v.slice().to_string())]
+    StringLiteral(String),
+    #[token("out")]
+    #[token("pri")]
+    Print,
+    #[token("in")]
+    #[token("get")]
+    Input,
+    #[token("outnum")]
+    PrintNumber,
+    #[token("innum")]
+    InputNumber,
+    #[token("peek")]
+    HeapLoad,
+    #[token("poke")]
+    HeapStore,
+    #[token("st")]
+    MoveToCell,
+    #[token("ld")]
+    LoadFromCell,
+    #[token("p1", |_| Target::P1)]
+    #[token("p2", |_| Target::P2)]
+    #[token("s1", |_| Target::S1)]
+    #[token("s2", |_| Target::S2)]
+    Target(Target),
+    #[regex(r";[^\n]*")]
+    Comment,
+    /// A comment starting with `#` rather than `;`, which is deprecated. A `#`
+    /// followed by digits is a decimal literal instead, unless the digits run
+    /// straight into other text (as in `#1st`), which older versions also read
+    /// as a comment.
+    #[regex(r"#([^0-9\n][^\n]*)?")]
+    #[regex(r"#[0-9]+[^0-9 \t\n\f(),{}][^\n]*")]
+    LegacyComment,
+    #[regex(r"[ \t\n\f]+", logos::skip)]
+    Whitespace,
+    #[token("raw")]
+    RawCode,
+    #[token("dialect")]
+    Dialect,
+    #[token("decimal", |_| Extension::Decimal)]
+    #[token("calls", |_| Extension::Calls)]
+    #[token("heap", |_| Extension::Heap)]
+    ExtensionName(Extension),
+    #[token("include")]
+    Include,
+    #[token("macro")]
+    Macro,
+    #[token("endmacro")]
+    EndMacro,
+    #[token("(")]
+    OpenParen,
+    #[token(")")]
+    CloseParen,
+    #[token(",")]
+    Comma,
+    #[token("{")]
+    OpenBrace,
+    #[token("}")]
+    CloseBrace,
+    #[token("if")]
+    If,
+    #[token("else")]
+    Else,
+    #[token("repeat")]
+    Repeat,
+    #[token("def")]
+    Define,
+    #[token("call")]
+    Call,
+    #[token("ret")]
+    Return,
+    #[regex(r"[A-Za-z_][A-Za-z0-9_]*", |v| v.slice().to_string(), priority = 1)]
+    Name(String),
+    #[regex(r"[A-Za-z_][A-Za-z0-9_]*:", |v| v.slice().trim_end_matches(':').to_string())]
+    Label(String),
+    #[error]
+    #[regex(r"\w+", priority = 0)]
+    Error,
+}
+
+/// The bytes subroutines are labelled with. Names which are one of these are
+/// labelled with themselves, and the rest are given the first one left over.
+const LABELS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+/// Where `statement` is, for errors about it found later.
+fn location(statement: &Spanned<Statement>) -> Spanned<()> {
+    Spanned {
+        node: (),
+        span: statement.span.clone(),
+        expanded_from: statement.expanded_from.clone(),
+    }
+}
+
+/// The labels given to subroutine names, and whether each has been defined.
+struct Subroutines {
+    reserved: Vec<u8>,
+    labels: HashMap<String, (u8, bool)>,
+    /// Where each subroutine is first called.
+    calls: HashMap<String, Spanned<()>>,
+}
+
+/// Add the names of subroutines in `statements` (and the blocks nested in
+/// them) which are one of [`LABELS`] to `reserved`.
+fn reserve(statements: &[Spanned<Statement>], reserved: &mut Vec<u8>) {
+    for statement in statements {
+        match &statement.node {
+            Statement::Define(name) | Statement::Call(name) => {
+                match name.as_bytes() {
+                    &[byte] if LABELS.contains(&byte) => reserved.push(byte),
+                    _ => {},
+                }
+            },
+            node => {
+                for block in node.blocks() {
+                    reserve(block, reserved);
+                }
+            },
+        }
+    }
+}
+
+/// Add the labels defined in `statements` (and the blocks nested in them) to
+/// `labels`.
+fn defined_labels(statements: &[Spanned<Statement>], labels: &mut HashSet<String>) {
+    for statement in statements {
+        match &statement.node {
+            Statement::Label(name) => {
+                labels.insert(name.clone());
+            },
+            node => {
+                for block in node.blocks() {
+                    defined_labels(block, labels);
+                }
+            },
+        }
+    }
+}
+
+/// `statements` with `suffix` added to the names of `labels`, wherever they're
+/// defined or jumped to.
+fn rename_labels(
+    statements: &[Spanned<Statement>],
+    labels: &HashSet<String>,
+    suffix: &str,
+) -> Vec<Spanned<Statement>> {
+    let rename = |name: &String| {
+        if labels.contains(name) {
+            format!("{}{}", name, suffix)
+        } else {
+            name.clone()
+        }
+    };
+    let block = |statements: &Vec<Spanned<Statement>>| rename_labels(statements, labels, suffix);
+    statements
+        .iter()
+        .map(|statement| {
+            let node = match &statement.node {
+                Statement::Label(name) => Statement::Label(rename(name)),
+                Statement::Jump(name) => Statement::Jump(rename(name)),
+                Statement::JumpIfZero(name) => Statement::JumpIfZero(rename(name)),
+                Statement::While { condition, body } => {
+                    Statement::While {
+                        condition: *condition,
+                        body: block(body),
+                    }
+                },
+                Statement::If { then, otherwise } => {
+                    Statement::If {
+                        then: block(then),
+                        otherwise: otherwise.as_ref().map(block),
+                    }
+                },
+                Statement::Repeat { count, body } => {
+                    Statement::Repeat {
+                        count: *count,
+                        body: block(body),
+                    }
+                },
+                node => node.clone(),
+            };
+            Spanned {
+                node,
+                span: statement.span.clone(),
+                expanded_from: statement.expanded_from.clone(),
+            }
+        })
+        .collect()
+}
+
+impl Subroutines {
+    fn new(statements: &[Spanned<Statement>]) -> Subroutines {
+        let mut reserved = vec![];
+        reserve(statements, &mut reserved);
+        Subroutines {
+            reserved,
+            labels: HashMap::new(),
+            calls: HashMap::new(),
+        }
+    }
+
+    fn label(&mut self, name: &str) -> Result<u8, String> {
+        if let Some(&(label, _)) = self.labels.get(name) {
+            return Ok(label);
+        }
+        let label = match name.as_bytes() {
+            &[byte] if LABELS.contains(&byte) => byte,
+            _ => {
+                let used: Vec<u8> = self.labels.values().map(|&(label, _)| label).collect();
+                *LABELS
+                    .iter()
+                    .find(|label| !self.reserved.contains(label) && !used.contains(label))
+                    .ok_or_else(|| {
+                        format!("Too many subroutines (at most {})", LABELS.len())
+                    })?
+            },
+        };
+        self.labels.insert(name.to_string(), (label, false));
+        Ok(label)
+    }
+
+    fn define(&mut self, name: &str) -> Result<u8, String> {
+        let label = self.label(name)?;
+        let defined = &mut self.labels.get_mut(name).unwrap().1;
+        if *defined {
+            return Err(format!("Subroutine {:?} is defined twice", name));
+        }
+        *defined = true;
+        Ok(label)
+    }
+
+    fn call(&mut self, name: &str, statement: &Spanned<Statement>) -> Result<u8, String> {
+        let label = self.label(name)?;
+        self.calls.entry(name.to_string()).or_insert_with(|| location(statement));
+        Ok(label)
+    }
+
+    fn any_defined(&self) -> bool {
+        self.labels.values().any(|&(_, defined)| defined)
+    }
+
+    /// The names of the subroutines defined so far.
+    fn defined(&self) -> HashSet<String> {
+        self.labels
+            .iter()
+            .filter(|(_, &(_, defined))| defined)
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Forget the definitions of subroutines other than `kept`.
+    fn undefine(&mut self, kept: &HashSet<String>) {
+        for (name, (_, defined)) in &mut self.labels {
+            *defined &= kept.contains(name);
+        }
+    }
+
+    /// Check that every subroutine called was defined, pointing at the first
+    /// call of each one which wasn't.
+    fn check(&self) -> Vec<Diagnostic> {
+        self.calls
+            .iter()
+            .filter(|(name, _)| !self.labels[*name].1)
+            .map(|(name, call)| {
+                Diagnostic::at(format!("Subroutine {:?} is called but never defined", name), call)
+            })
+            .collect()
+    }
+}
+
+/// The offsets of labels in the program being assembled, and the jumps to
+/// them, which are only filled in once the whole program has been.
+#[derive(Default)]
+struct Labels {
+    offsets: HashMap<String, usize>,
+    /// The label each jump goes to, the offset of the digit to fill in, and
+    /// where the jump is.
+    jumps: Vec<(String, usize, Spanned<()>)>,
+    /// The label each jump in code which isn't kept (the body of a `repeat 0`)
+    /// goes to, and where the jump is, which are only checked to exist.
+    unkept: Vec<(String, Spanned<()>)>,
+}
+
+/// The name of the label `name` as it's written, without the suffixes each
+/// expansion of a macro (`@`) and each time through a `repeat` (`#`) give it.
+fn written(name: &str) -> &str {
+    name.split(['@', '#']).next().unwrap()
+}
+
+impl Labels {
+    fn define(&mut self, name: String, offset: usize) -> Result<(), String> {
+        if self.offsets.contains_key(&name) {
+            return Err(format!("Label {:?} is defined twice", written(&name)));
+        }
+        self.offsets.insert(name, offset);
+        Ok(())
+    }
+
+    /// Add a skip to the label `name`, to be filled in by
+    /// [`resolve`](Labels::resolve).
+    fn jump(&mut self, name: String, program: &mut String, statement: &Spanned<Statement>) {
+        self.jumps.push((name, program.len(), location(statement)));
+        program.push('0');
+    }
+
+    /// Fill in the distance of every jump. Skips only go forward, by at most 9
+    /// bytes, so jumps to labels anywhere else are errors.
+    fn resolve(&self, program: &mut String) -> Vec<Diagnostic> {
+        let mut errors = vec![];
+        for (name, jump) in &self.unkept {
+            if !self.offsets.contains_key(name) {
+                let message = format!("Label {:?} is jumped to but never defined", written(name));
+                errors.push(Diagnostic::at(message, jump));
+            }
+        }
+        for (name, offset, jump) in &self.jumps {
+            let written = written(name);
+            let distance = match self.offsets.get(name).map(|target| target.checked_sub(offset + 1)) {
+                None => Err(format!("Label {:?} is jumped to but never defined", written)),
+                Some(Some(0)) => Err(format!(
+                    "Label {:?} is right after the jump to it, so there's nothing to skip",
+                    written
+                )),
+                Some(Some(distance @ 1..=9)) => Ok(distance),
+                Some(Some(distance)) => Err(format!(
+                    "Label {:?} is {} bytes past the jump to it, but a jump can skip at most 9",
+                    written, distance
+                )),
+                Some(None) => Err(format!(
+                    "Label {:?} is before the jump to it, but jumps only go forward",
+                    written
+                )),
+            };
+            match distance {
+                Ok(distance) => {
+                    program.replace_range(*offset..offset + 1, &distance.to_string())
+                },
+                Err(message) => errors.push(Diagnostic::at(message, jump)),
+            }
+        }
+        errors
+    }
+}
+
+/// Moves the top of the secondary stack to the top of the primary stack,
+/// using the cell.
+const FROM_S1: &str = "X}x{X}X{X";
+/// Moves the value below the top of the secondary stack to the top of the
+/// primary stack, using the cell.
+const FROM_S2: &str = "Xx}x{X}X{X";
+
+/// The code which moves `target` to the top of the primary stack.
+fn fetch(target: Target) -> &'static str {
+    match target {
+        Target::P1 => "",
+        Target::P2 => "x",
+        Target::S1 => FROM_S1,
+        Target::S2 => FROM_S2,
+    }
+}
+
+/// The code for a binary operation `op` with `operands`, which is
+/// [`Kind::Symmetric`] unless `asymmetric`.
+fn binary(op: &str, operands: &[Operand], asymmetric: bool) -> Result<String, String> {
+    // a lone operand is moved to the top, with the other argument below it
+    let swap = if asymmetric { "x" } else { "" };
+    let code = match operands {
+        [] => op.to_string(),
+        [Operand::Target(a), Operand::Target(b)] => {
+            // the left hand side ends up on top, with the right below it
+            let (before, after) = match (a, b) {
+                (Target::P1, Target::P2) => (String::new(), ""),
+                (Target::P2, Target::P1) => (swap.to_string(), ""),
+                (Target::P1, Target::S1 | Target::S2) => (format!("{}{}", fetch(*b), swap), ""),
+                (Target::P2, Target::S1 | Target::S2) => (format!("x{}{}", fetch(*b), swap), ""),
+                (Target::S1 | Target::S2, Target::P1) => (fetch(*a).to_string(), ""),
+                (Target::S1 | Target::S2, Target::P2) => (format!("x{}", fetch(*a)), ""),
+                // operate on the secondary stack, and move the result back
+                (Target::S1, Target::S2) => ("X".to_string(), "}x{X}X{X"),
+                (Target::S2, Target::S1) => (format!("X{}", swap), "}x{X}X{X"),
+                (a, _) => {
+                    return Err(format!(
+                        "Bad operation: {:?} {} {:?} - left may not be the same as right \
+                         (consider duplicating)",
+                        a.name(),
+                        op,
+                        a.name()
+                    ));
+                },
+            };
+            format!("{}{}{}", before, op, after)
+        },
+        [Operand::Target(a), Operand::Literal(b)] => format!("{}{}{}{}", fetch(*a), b, swap, op),
+        [Operand::Literal(a), Operand::Target(b)] => format!("{}{}{}", fetch(*b), a, op),
+        [Operand::Literal(a), Operand::Literal(b)] => format!("{}{}{}", b, a, op),
+        [Operand::Target(Target::P1)] if asymmetric => {
+            return Err(format!("Bad operation: p1 (implicit) {} p1 (explicit)", op));
+        },
+        [Operand::Target(Target::P1)] => {
+            return Err(format!("Bad operation: p1 (explicit) {} p1 (implicit)", op));
+        },
+        [Operand::Target(Target::P2)] => op.to_string(),
+        [Operand::Target(a)] => format!("{}{}{}", fetch(*a), swap, op),
+        [Operand::Literal(a)] => format!("{}{}{}", a, swap, op),
+        _ => unreachable!("binary operations have at most two operands"),
+    };
+    Ok(code)
+}
+
+/// Add the code which pushes `bytes`, reversed byte by byte so that they're
+/// printed in the order they're written. A `"…"` push can't hold a `"`, nor
+/// brackets (which would be matched with those of loops), nor bytes which
+/// aren't ASCII (which wouldn't be text), so those are pushed on their own
+/// between the runs of the rest.
+fn push_string(program: &mut String, bytes: &[u8]) {
+    let mut quoted = false;
+    for &byte in bytes.iter().rev() {
+        let plain = byte.is_ascii() && !b"\"[]()".contains(&byte);
+        if plain != quoted {
+            program.push('"');
+            quoted = plain;
+        }
+        match byte {
+            _ if plain => program.push(byte as char),
+            b'"' => program.push_str("'\""),
+            _ => program.push_str(&format!("#{:02x}", byte)),
+        }
+    }
+    if quoted {
+        program.push('"');
+    }
+}
+
+/// Split `source` into tokens, describing each one (and its text) in a line,
+/// so that tests can pin down how files are read.
+pub fn tokens(source: &str) -> Vec<String> {
+    Token::lexer(source)
+        .spanned()
+        .map(|(token, span)| format!("{:?} {:?}", token, &source[span]))
+        .collect()
+}
+
+/// The lines (counting from 1) of comments in `source` which start with `#`,
+/// which is deprecated in favour of `;`.
+pub fn legacy_comments(source: &str) -> Vec<usize> {
+    Token::lexer(source)
+        .spanned()
+        .filter(|(token, _)| *token == Token::LegacyComment)
+        .map(|(_, span)| source[..span.start].matches('\n').count() + 1)
+        .collect()
+}
+
+/// Assemble `source` into a `.cel` program, or find every error in it. The
+/// only files it can include are those in the standard library.
+pub fn assemble(source: &str) -> Result<String, Vec<Diagnostic>> {
+    let mut sources = Sources::default();
+    let file = sources.add("", source);
+    assemble_file(&mut sources, file)
+}
+
+/// Assemble the file `file` in `sources`, adding the files it includes to
+/// them.
+pub fn assemble_file(sources: &mut Sources, file: usize) -> Result<String, Vec<Diagnostic>> {
+    lower(&ast::parse(sources, &[file])?)
+}
+
+/// Assemble the files `files` in `sources` into one program, one after
+/// another, along with where the code for each statement starts and its span
+/// (as [`lower_with_spans`] gives them).
+pub fn assemble_files(
+    sources: &mut Sources,
+    files: &[usize],
+) -> Result<(String, Spans), Vec<Diagnostic>> {
+    lower_with_spans(&ast::parse(sources, files)?)
+}
+
+/// A source map saying which line of `sources` each byte of a program came
+/// from, given the spans of its statements.
+pub fn source_map(sources: &Sources, spans: &[(usize, Span)]) -> SourceMap {
+    let mut map = SourceMap::default();
+    for (offset, span) in spans {
+        let (file, line, column) = sources.position(span.start);
+        map.add(*offset, &file.path, line, column);
+    }
+    map
+}
+
+/// A listing of `program`, given the spans of its statements: each line of
+/// `sources` which produced code, in the order the code is in, after the
+/// offset of the code and the code itself. Control characters in the code are
+/// escaped.
+pub fn listing(sources: &Sources, program: &str, spans: &[(usize, Span)]) -> String {
+    // offsets count from after the header
+    let (_, tape) = Dialect::parse(program.as_bytes()).expect("the header is one we wrote");
+    let tape = &program[program.len() - tape.len()..];
+    // the offset and code for each line, and the file and line it is, merging
+    // runs of statements on the same line
+    let mut lines: Vec<(usize, String, usize, usize)> = vec![];
+    for (i, (offset, span)) in spans.iter().enumerate() {
+        let end = spans.get(i + 1).map_or(tape.len(), |(next, _)| *next);
+        if end == *offset {
+            continue;
+        }
+        let mut code = String::new();
+        for c in tape[*offset..end].chars() {
+            if c.is_control() {
+                code.extend(c.escape_default());
+            } else {
+                code.push(c);
+            }
+        }
+        let file = sources.index(span.start);
+        let (_, line, _) = sources.position(span.start);
+        match lines.last_mut() {
+            Some(last) if (last.2, last.3) == (file, line) => last.1.push_str(&code),
+            _ => lines.push((*offset, code, file, line)),
+        }
+    }
+    let mut out = String::new();
+    for (offset, code, file, line) in lines {
+        let file = sources.file(file);
+        let text = file.text.lines().nth(line - 1).unwrap_or("");
+        out.push_str(&format!(
+            "{:>5}  {:<12}  {}:{}  {}\n",
+            offset,
+            code,
+            file.path,
+            line,
+            text.trim_end()
+        ));
+    }
+    out
+}
+
+/// Lower parsed assembly into a `.cel` program, checking that labels,
+/// subroutines and extensions are used correctly.
+pub fn lower(statements: &[Spanned<Statement>]) -> Result<String, Vec<Diagnostic>> {
+    lower_with_spans(statements).map(|(program, _)| program)
+}
+
+/// Lower parsed assembly as [`lower`] does, along with the offset into the
+/// program (after its header) that the code for each statement starts at and
+/// the statement's span, in order. Blocks are given again after their bodies,
+/// for the code which closes them.
+pub fn lower_with_spans(
+    statements: &[Spanned<Statement>],
+) -> Result<(String, Spans), Vec<Diagnostic>> {
+    let mut lowering = Lowering {
+        subroutines: Subroutines::new(statements),
+        labels: Labels::default(),
+        program: String::new(),
+        dialect: Dialect::default(),
+        errors: vec![],
+        spans: vec![],
+        repetitions: 0,
+        falls_through: true,
+        too_long: false,
+    };
+    lowering.statements(statements);
+    let Lowering {
+        subroutines,
+        labels,
+        mut program,
+        dialect,
+        mut errors,
+        spans,
+        ..
+    } = lowering;
+    errors.extend(subroutines.check());
+    errors.extend(labels.resolve(&mut program));
+    errors.sort_by_key(|error| error.span.start);
+    // the body of a `repeat` is lowered, and so checked, more than once
+    let mut found = vec![];
+    errors.retain(|error| {
+        let new = !found.contains(error);
+        if new {
+            found.push(error.clone());
+        }
+        new
+    });
+
+    if errors.is_empty() {
+        Ok((dialect.header() + &program, spans))
+    } else {
+        Err(errors)
+    }
+}
+
+/// The offsets into a program that the code for statements starts at, and the
+/// statements' spans.
+pub type Spans = Vec<(usize, Span)>;
+
+/// How long repeated code can make a program, so that a `repeat` (or several
+/// nested ones) can't take the assembler forever.
+const MAX_PROGRAM_LENGTH: usize = 1 << 24;
+
+/// The state of the program being lowered.
+struct Lowering {
+    subroutines: Subroutines,
+    labels: Labels,
+    program: String,
+    dialect: Dialect,
+    errors: Vec<Diagnostic>,
+    spans: Spans,
+    /// How many times the bodies of `repeat`s have been lowered, which numbers
+    /// the labels in each.
+    repetitions: usize,
+    /// Whether execution can continue past the code lowered so far, rather
+    /// than it ending with a `ret`, a `jmp` to a label or raw code ending with a `.`.
+    falls_through: bool,
+    /// Whether a `repeat` has stopped because the program got too long.
+    too_long: bool,
+}
+
+impl Lowering {
+    /// Lower `statements` for the errors in them, without keeping their code
+    /// (or the labels, subroutines and extensions they define).
+    fn check(&mut self, statements: &[Spanned<Statement>]) {
+        let (length, spans) = (self.program.len(), self.spans.len());
+        let jumps = self.labels.jumps.len();
+        let (dialect, falls_through) = (self.dialect.clone(), self.falls_through);
+        let defined = self.subroutines.defined();
+        let mut labels = HashSet::new();
+        defined_labels(statements, &mut labels);
+        self.repeat(statements, &labels);
+        self.program.truncate(length);
+        self.spans.truncate(spans);
+        let unkept = self.labels.jumps.drain(jumps..).map(|(name, _, jump)| (name, jump));
+        self.labels.unkept.extend(unkept);
+        self.dialect = dialect;
+        self.falls_through = falls_through;
+        self.subroutines.undefine(&defined);
+    }
+
+    /// Lower the body of a `repeat` once more, with new names for the `labels`
+    /// defined in it, as each expansion of a macro has.
+    fn repeat(&mut self, body: &[Spanned<Statement>], labels: &HashSet<String>) {
+        self.repetitions += 1;
+        let suffix = format!("#{}", self.repetitions);
+        self.statements(&rename_labels(body, labels, &suffix));
+    }
+
+    fn statements(&mut self, statements: &[Spanned<Statement>]) {
+        for statement in statements {
+            self.statement(statement);
+        }
+    }
+
+    /// Say that the code from here on is for `statement`.
+    fn mark(&mut self, statement: &Spanned<Statement>) {
+        self.spans.push((self.program.len(), statement.span.clone()));
+    }
+
+    fn statement(&mut self, statement: &Spanned<Statement>) {
+        self.mark(statement);
+        let (needs, name) = match &statement.node {
+            Statement::Op { mnemonic, .. } => (mnemonic.extension(), mnemonic.name()),
+            Statement::Define(_) => (Some(Extension::Calls), "def"),
+            Statement::Call(_) => (Some(Extension::Calls), "call"),
+            _ => (None, ""),
+        };
+        // carry on lowering it anyway, so there aren't errors about it later
+        if let Some(extension) = needs.filter(|&extension| !self.dialect.has(extension)) {
+            self.errors.push(Diagnostic::at(
+                format!(
+                    "{:?} needs the {} extension (add `dialect {}` first)",
+                    name, extension, extension
+                ),
+                statement,
+            ));
+        }
+        let program = &mut self.program;
+        let result = match &statement.node {
+            Statement::Op { mnemonic, operands } => {
+                let op = mnemonic.instruction();
+                let operands: Vec<Operand> =
+                    operands.iter().map(|operand| operand.node.clone()).collect();
+                match mnemonic.kind() {
+                    Kind::Symmetric => binary(op, &operands, false),
+                    Kind::Asymmetric => binary(op, &operands, true),
+                    Kind::Unary | Kind::Nullary => {
+                        let fetched = match operands.first() {
+                            Some(Operand::Target(target)) => fetch(*target),
+                            Some(Operand::Literal(literal)) => literal,
+                            None => "",
+                        };
+                        Ok(format!("{}{}", fetched, op))
+                    },
+                }
+                .map(|code| program.push_str(&code))
+            },
+            Statement::Push(Value::Literal(literal)) => {
+                program.push_str(literal);
+                Ok(())
+            },
+            Statement::Push(Value::String(bytes)) => {
+                push_string(program, bytes);
+                Ok(())
+            },
+            Statement::Skip(distance) => {
+                program.push_str(&distance.to_string());
+                Ok(())
+            },
+            Statement::Jump(name) => {
+                self.labels.jump(name.clone(), program, statement);
+                Ok(())
+            },
+            // if the value is zero, skip the 1 and jump to the label, otherwise
+            // skip the jump
+            Statement::JumpIfZero(name) => {
+                program.push_str("?1");
+                self.labels.jump(name.clone(), program, statement);
+                Ok(())
+            },
+            Statement::Label(name) => self.labels.define(name.clone(), program.len()),
+            Statement::Raw(code) => {
+                program.push_str(code);
+                Ok(())
+            },
+            // the extensions are still enabled, so there aren't errors about
+            // using them later
+            Statement::Dialect(extensions) => {
+                for &extension in extensions {
+                    self.dialect.enable(extension);
+                }
+                if program.is_empty() {
+                    Ok(())
+                } else {
+                    Err("The dialect must be given before any code".to_string())
+                }
+            },
+            // the program stops before its first subroutine, rather than running
+            // into it and returning without a call
+            Statement::Define(name) => {
+                let halt = self.falls_through && !self.subroutines.any_defined();
+                self.subroutines.define(name).map(|label| {
+                    if halt {
+                        program.push('.');
+                    }
+                    program.push('D');
+                    program.push(label as char);
+                })
+            },
+            Statement::Call(name) => self.subroutines.call(name, statement).map(|label| {
+                program.push('C');
+                program.push(label as char);
+            }),
+            Statement::While { condition, body } => {
+                let (start, end) = match condition {
+                    Condition::NonZero => ('[', ']'),
+                    Condition::Zero => ('(', ')'),
+                };
+                program.push(start);
+                self.statements(body);
+                self.mark(statement);
+                self.program.push(end);
+                Ok(())
+            },
+            // the value is consumed on the way in, and a zero is pushed to
+            // leave (`]` consumes it)
+            Statement::If {
+                then,
+                otherwise: None,
+            } => {
+                program.push('[');
+                self.statements(then);
+                self.mark(statement);
+                self.program.push_str("#00]");
+                Ok(())
+            },
+            // a zero is put below the value, and dropped if `then` is run, which
+            // leaves a one to skip `otherwise`; if it isn't, the zero is left to
+            // run `otherwise` (which pushes a one to leave)
+            Statement::If {
+                then,
+                otherwise: Some(otherwise),
+            } => {
+                program.push_str("#00x[`");
+                self.statements(then);
+                self.mark(statement);
+                self.program.push_str("#01#00](");
+                self.statements(otherwise);
+                self.mark(statement);
+                self.program.push_str("#01)");
+                Ok(())
+            },
+            // the body is still checked, though none of it is kept
+            Statement::Repeat { count: 0, body } => {
+                self.check(body);
+                Ok(())
+            },
+            Statement::Repeat { count, body } => {
+                let mut labels = HashSet::new();
+                defined_labels(body, &mut labels);
+                let mut result = Ok(());
+                for _ in 0..*count {
+                    if self.program.len() > MAX_PROGRAM_LENGTH {
+                        // which is only reported by the innermost `repeat`
+                        if !self.too_long {
+                            self.too_long = true;
+                            result = Err(format!(
+                                "Repeating this makes the program more than {} bytes long",
+                                MAX_PROGRAM_LENGTH
+                            ));
+                        }
+                        break;
+                    }
+                    self.repeat(body, &labels);
+                }
+                result
+            },
+        };
+        if let Err(message) = result {
+            self.errors.push(Diagnostic::at(message, statement));
+        }
+        self.falls_through = match &statement.node {
+            Statement::Op {
+                mnemonic: Mnemonic::Return,
+                ..
+            }
+            | Statement::Jump(_) => false,
+            Statement::Raw(code) => !code.ends_with('.'),
+            Statement::Dialect(_) | Statement::Repeat { count: 0, .. } => self.falls_through,
+            _ => true,
+        };
+    }
+}
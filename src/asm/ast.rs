@@ -0,0 +1,805 @@
+//! Assembly parsed into statements, before it's lowered into a `.cel` program
+//! by [`lower`](super::lower).
+
+use std::iter::Peekable;
+use std::ops::Range;
+use std::vec::IntoIter;
+
+use super::diagnostic::Diagnostic;
+use super::macros::{self, Lexeme};
+use super::source::Sources;
+use super::Token;
+use crate::dialect::Extension;
+
+/// A range of bytes in the source, numbered as [`Sources`] numbers them.
+pub type Span = Range<usize>;
+
+/// Something parsed from the source, and where it was.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+    /// The macro calls it was expanded from, innermost first.
+    pub expanded_from: Vec<Call>,
+}
+
+/// A call of a macro, which code was expanded from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    pub name: String,
+    pub span: Span,
+}
+
+/// A value on top of one of the stacks, which an operation moves into place
+/// before it's applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// The top of the primary stack
+    P1,
+    /// The value below the top of the primary stack
+    P2,
+    /// The top of the secondary stack
+    S1,
+    /// The value below the top of the secondary stack
+    S2,
+}
+
+impl Target {
+    pub fn name(self) -> &'static str {
+        match self {
+            Target::P1 => "p1",
+            Target::P2 => "p2",
+            Target::S1 => "s1",
+            Target::S2 => "s2",
+        }
+    }
+}
+
+/// Where an operation takes one of its arguments from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operand {
+    Target(Target),
+    /// The code which pushes a constant, such as `#2a` or `'a`.
+    Literal(String),
+}
+
+/// What a `push` pushes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// The code which pushes a constant, such as `#2a` or `'a`.
+    Literal(String),
+    /// The bytes of a string, with its escapes replaced by what they stand
+    /// for, which are pushed so that they're printed in the order they're
+    /// written.
+    String(Vec<u8>),
+}
+
+/// How many operands a [`Mnemonic`] takes, and how they're arranged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Nullary,
+    Unary,
+    /// Binary, where the order of the arguments doesn't matter.
+    Symmetric,
+    /// Binary, where the first argument is the left hand side.
+    Asymmetric,
+}
+
+/// A mnemonic which assembles to a single instruction, after moving its
+/// operands into place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mnemonic {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    BitwiseNegate,
+    LogicalNot,
+    BitwiseAnd,
+    BitwiseOr,
+    BitwiseXor,
+    Equal,
+    Less,
+    Greater,
+    WhileNonZero,
+    WhileZero,
+    NextNonZero,
+    NextZero,
+    Duplicate,
+    Drop,
+    SwapValues,
+    SwapStacks,
+    Conditional,
+    Print,
+    Input,
+    PrintNumber,
+    InputNumber,
+    MoveToCell,
+    LoadFromCell,
+    HeapLoad,
+    HeapStore,
+    Return,
+}
+
+impl Mnemonic {
+    /// How the mnemonic is written (the first spelling, where there are
+    /// several).
+    pub fn name(self) -> &'static str {
+        match self {
+            Mnemonic::Add => "add",
+            Mnemonic::Subtract => "sub",
+            Mnemonic::Multiply => "mul",
+            Mnemonic::Divide => "div",
+            Mnemonic::Modulo => "mod",
+            Mnemonic::BitwiseNegate => "neg",
+            Mnemonic::LogicalNot => "not",
+            Mnemonic::BitwiseAnd => "and",
+            Mnemonic::BitwiseOr => "or",
+            Mnemonic::BitwiseXor => "xor",
+            Mnemonic::Equal => "eq",
+            Mnemonic::Less => "lt",
+            Mnemonic::Greater => "gt",
+            Mnemonic::WhileNonZero => "while nonzero",
+            Mnemonic::WhileZero => "while zero",
+            Mnemonic::NextNonZero => "cont nonzero",
+            Mnemonic::NextZero => "cont zero",
+            Mnemonic::Duplicate => "dup",
+            Mnemonic::Drop => "drop",
+            Mnemonic::SwapValues => "swap values",
+            Mnemonic::SwapStacks => "swap stacks",
+            Mnemonic::Conditional => "jz",
+            Mnemonic::Print => "out",
+            Mnemonic::Input => "in",
+            Mnemonic::PrintNumber => "outnum",
+            Mnemonic::InputNumber => "innum",
+            Mnemonic::MoveToCell => "st",
+            Mnemonic::LoadFromCell => "ld",
+            Mnemonic::HeapLoad => "peek",
+            Mnemonic::HeapStore => "poke",
+            Mnemonic::Return => "ret",
+        }
+    }
+
+    /// The instruction this assembles to.
+    pub fn instruction(self) -> &'static str {
+        match self {
+            Mnemonic::Add => "+",
+            Mnemonic::Subtract => "-",
+            Mnemonic::Multiply => "*",
+            Mnemonic::Divide => "/",
+            Mnemonic::Modulo => "%",
+            Mnemonic::BitwiseNegate => "~",
+            Mnemonic::LogicalNot => "!",
+            Mnemonic::BitwiseAnd => "&",
+            Mnemonic::BitwiseOr => "|",
+            Mnemonic::BitwiseXor => "^",
+            Mnemonic::Equal => "=",
+            Mnemonic::Less => "<",
+            Mnemonic::Greater => ">",
+            Mnemonic::WhileNonZero => "[",
+            Mnemonic::WhileZero => "(",
+            Mnemonic::NextNonZero => "]",
+            Mnemonic::NextZero => ")",
+            Mnemonic::Duplicate => ":",
+            Mnemonic::Drop => "`",
+            Mnemonic::SwapValues => "x",
+            Mnemonic::SwapStacks => "X",
+            Mnemonic::Conditional => "?",
+            Mnemonic::Print => ";",
+            Mnemonic::Input => "@",
+            Mnemonic::PrintNumber => "n",
+            Mnemonic::InputNumber => "N",
+            Mnemonic::MoveToCell => "{",
+            Mnemonic::LoadFromCell => "}",
+            Mnemonic::HeapLoad => "g",
+            Mnemonic::HeapStore => "p",
+            Mnemonic::Return => "R",
+        }
+    }
+
+    pub fn kind(self) -> Kind {
+        match self {
+            Mnemonic::Add
+            | Mnemonic::Multiply
+            | Mnemonic::BitwiseAnd
+            | Mnemonic::BitwiseOr
+            | Mnemonic::BitwiseXor
+            | Mnemonic::Equal => Kind::Symmetric,
+            Mnemonic::Subtract
+            | Mnemonic::Divide
+            | Mnemonic::Modulo
+            | Mnemonic::Less
+            | Mnemonic::Greater => Kind::Asymmetric,
+            Mnemonic::BitwiseNegate
+            | Mnemonic::LogicalNot
+            | Mnemonic::Duplicate
+            | Mnemonic::Drop
+            | Mnemonic::Conditional
+            | Mnemonic::Print
+            | Mnemonic::PrintNumber
+            | Mnemonic::MoveToCell
+            | Mnemonic::HeapLoad
+            | Mnemonic::HeapStore => Kind::Unary,
+            Mnemonic::WhileNonZero
+            | Mnemonic::WhileZero
+            | Mnemonic::NextNonZero
+            | Mnemonic::NextZero
+            | Mnemonic::SwapValues
+            | Mnemonic::SwapStacks
+            | Mnemonic::Input
+            | Mnemonic::InputNumber
+            | Mnemonic::LoadFromCell
+            | Mnemonic::Return => Kind::Nullary,
+        }
+    }
+
+    /// The extension which must be enabled to use this mnemonic, if any.
+    pub fn extension(self) -> Option<Extension> {
+        match self {
+            Mnemonic::PrintNumber | Mnemonic::InputNumber => Some(Extension::Decimal),
+            Mnemonic::Return => Some(Extension::Calls),
+            Mnemonic::HeapLoad | Mnemonic::HeapStore => Some(Extension::Heap),
+            _ => None,
+        }
+    }
+}
+
+/// What a block loops or branches on: the value on top of the primary stack,
+/// which is consumed each time it's tested.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    NonZero,
+    Zero,
+}
+
+/// The most times a `repeat` can write out its body.
+pub const MAX_REPEAT: usize = 65_536;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Statement {
+    /// A mnemonic, with up to two operands (as many as its [`Kind`] allows).
+    Op {
+        mnemonic: Mnemonic,
+        operands: Vec<Spanned<Operand>>,
+    },
+    /// `push` and what it pushes
+    Push(Value),
+    /// `jmp` and a distance
+    Skip(u8),
+    /// `jmp` and a label
+    Jump(String),
+    /// `jz` and a label
+    JumpIfZero(String),
+    /// A label (written `name:`), which marks the offset a jump goes to
+    Label(String),
+    /// `raw` and the code between its quotes
+    Raw(String),
+    /// `dialect` and the extensions it enables
+    Dialect(Vec<Extension>),
+    /// `def` and the name of the subroutine it starts
+    Define(String),
+    /// `call` and the name of the subroutine
+    Call(String),
+    /// `while nonzero { … }` or `while zero { … }`, which runs the body while
+    /// the value on top is (or isn't) zero, testing it before each time
+    While {
+        condition: Condition,
+        body: Vec<Spanned<Statement>>,
+    },
+    /// `if { … }`, optionally followed by `else { … }`, which runs `then` if
+    /// the value on top isn't zero, and `otherwise` if it is
+    If {
+        then: Vec<Spanned<Statement>>,
+        otherwise: Option<Vec<Spanned<Statement>>>,
+    },
+    /// `repeat` and a count (at most [`MAX_REPEAT`]), followed by `{ … }`,
+    /// which runs the body that many times
+    Repeat {
+        count: usize,
+        body: Vec<Spanned<Statement>>,
+    },
+}
+
+impl Statement {
+    /// The blocks of statements nested in it.
+    pub fn blocks(&self) -> Vec<&[Spanned<Statement>]> {
+        match self {
+            Statement::While { body, .. } | Statement::Repeat { body, .. } => vec![body],
+            Statement::If { then, otherwise } => {
+                std::iter::once(then).chain(otherwise).map(Vec::as_slice).collect()
+            },
+            _ => vec![],
+        }
+    }
+}
+
+type Lexemes = Peekable<IntoIter<Lexeme>>;
+
+/// The error for a token which couldn't be read.
+pub(super) fn invalid(text: &str, span: Span) -> Diagnostic {
+    match text.strip_prefix('#') {
+        Some(digits) if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) => {
+            Diagnostic::new(
+                format!("Decimal literal {} is out of range (at most 255)", text),
+                span,
+            )
+        },
+        _ => Diagnostic::new(format!("Found invalid token {:?}", text), span),
+    }
+}
+
+/// The value of a literal, such as `#2a` or `'a`.
+fn literal_value(literal: &str) -> usize {
+    match literal.strip_prefix('#') {
+        Some(hex) => usize::from_str_radix(hex, 16).unwrap(),
+        None => literal.chars().nth(1).unwrap() as usize,
+    }
+}
+
+/// The bytes of the string literal `string`, which is the text of `lexeme`
+/// (quotes and all), with its escapes replaced by what they stand for.
+fn unescape(lexeme: &Lexeme, string: &str) -> Result<Vec<u8>, Diagnostic> {
+    let mut bytes = vec![];
+    let mut chars = string[1..string.len() - 1].char_indices();
+    while let Some((index, c)) = chars.next() {
+        if c != '\\' {
+            bytes.extend(c.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+        // where the escape is in the source, after the opening quote
+        let start = lexeme.span.start + 1 + index;
+        let (_, escaped) = chars.next().expect("the lexer only allows `\\` before a character");
+        let error = |length: usize, message: String| {
+            Diagnostic::new(message, start..start + length).within(&lexeme.expanded_from)
+        };
+        bytes.push(match escaped {
+            'n' => b'\n',
+            't' => b'\t',
+            'r' => b'\r',
+            '0' => 0,
+            '\\' | '"' | '\'' => escaped as u8,
+            'x' => {
+                let digits: String = chars.clone().take(2).map(|(_, c)| c).collect();
+                if digits.len() != 2 || !digits.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+                    return Err(error(
+                        2,
+                        format!("Expected two hex digits after `\\x` but found {:?}", digits),
+                    ));
+                }
+                chars.nth(1);
+                u8::from_str_radix(&digits, 16).unwrap()
+            },
+            _ => {
+                return Err(error(
+                    1 + escaped.len_utf8(),
+                    format!("Unknown escape `\\{}` in string", escaped),
+                ))
+            },
+        });
+    }
+    Ok(bytes)
+}
+
+/// Take the next token if it's an operand, returning the operand.
+fn operand(lexemes: &mut Lexemes) -> Option<Spanned<Operand>> {
+    let lexeme = lexemes
+        .next_if(|lexeme| matches!(lexeme.token, Token::Target(_) | Token::Literal(_)))?;
+    let node = match lexeme.token {
+        Token::Target(target) => Operand::Target(target),
+        Token::Literal(literal) => Operand::Literal(literal),
+        _ => unreachable!(),
+    };
+    Some(Spanned {
+        node,
+        span: lexeme.span,
+        expanded_from: lexeme.expanded_from,
+    })
+}
+
+/// The next lexeme and its text, or an error saying that `expected` was, at
+/// `at` (the lexeme it should have followed).
+fn expect<'a>(
+    lexemes: &mut Lexemes,
+    sources: &'a Sources,
+    expected: &str,
+    at: &Lexeme,
+) -> Result<(Lexeme, &'a str), Diagnostic> {
+    match lexemes.next() {
+        Some(lexeme)
+            if lexeme.token == Token::Error && sources.text(lexeme.span.clone()).starts_with('#') =>
+        {
+            Err(invalid(sources.text(lexeme.span.clone()), lexeme.span.clone())
+                .within(&lexeme.expanded_from))
+        },
+        Some(lexeme) => {
+            let text = sources.text(lexeme.span.clone());
+            Ok((lexeme, text))
+        },
+        None => Err(at.error(format!("Expected {} but found EOF", expected))),
+    }
+}
+
+/// Parse the files `files` as one program, one after another, without
+/// checking that the statements make sense together (which
+/// [`lower`](super::lower) does). Macros are expanded first, and parsing
+/// carries on after an error, so that every error in the source is found.
+pub fn parse(
+    sources: &mut Sources,
+    files: &[usize],
+) -> Result<Vec<Spanned<Statement>>, Vec<Diagnostic>> {
+    let mut lexemes = macros::expand(sources, files)?.into_iter().peekable();
+    let sources = &*sources;
+    let mut statements = vec![];
+    let mut errors = vec![];
+    while let Some(lexeme) = lexemes.next() {
+        match next_statement(lexeme, &mut lexemes, sources, &mut errors) {
+            Ok(statement) => statements.push(statement),
+            Err(error) => errors.push(error),
+        }
+    }
+    // errors in blocks are found before the errors in what they're nested in
+    errors.sort_by_key(|error| error.span.start);
+    // an argument used more than once in a macro would be reported each time
+    errors.dedup();
+    if errors.is_empty() {
+        Ok(statements)
+    } else {
+        Err(errors)
+    }
+}
+
+/// The `{` which should follow `at`, opening a block. Anything else is left to
+/// be parsed as the next statement.
+fn opening(lexemes: &mut Lexemes, sources: &Sources, at: &Lexeme) -> Result<Lexeme, Diagnostic> {
+    if let Some(open) = lexemes.next_if(|lexeme| lexeme.token == Token::OpenBrace) {
+        return Ok(open);
+    }
+    match lexemes.peek() {
+        Some(lexeme) => Err(lexeme.error(format!(
+            "Expected `{{` but found {:?}",
+            sources.text(lexeme.span.clone())
+        ))),
+        None => Err(at.error("Expected `{` but found EOF")),
+    }
+}
+
+/// Skip the block opened by the next lexeme, if it's a `{`, so that the error
+/// before it isn't followed by errors about its braces.
+fn skip_block(
+    lexemes: &mut Lexemes,
+    sources: &Sources,
+    errors: &mut Vec<Diagnostic>,
+) -> Result<(), Diagnostic> {
+    if let Some(open) = lexemes.next_if(|lexeme| lexeme.token == Token::OpenBrace) {
+        block(&open, lexemes, sources, errors)?;
+    }
+    Ok(())
+}
+
+/// Parse the statements of the block opened by `open`, up to and including the
+/// `}` closing it, which is returned with them. Errors in the statements are
+/// added to `errors`, so that parsing can carry on.
+fn block(
+    open: &Lexeme,
+    lexemes: &mut Lexemes,
+    sources: &Sources,
+    errors: &mut Vec<Diagnostic>,
+) -> Result<(Vec<Spanned<Statement>>, Lexeme), Diagnostic> {
+    let mut statements = vec![];
+    loop {
+        match lexemes.next() {
+            Some(lexeme) if lexeme.token == Token::CloseBrace => return Ok((statements, lexeme)),
+            Some(lexeme) => {
+                match next_statement(lexeme, lexemes, sources, errors) {
+                    Ok(statement) => statements.push(statement),
+                    Err(error) => errors.push(error),
+                }
+            },
+            None => return Err(open.error("This block is never closed with `}`")),
+        }
+    }
+}
+
+/// Parse the statement starting with `first`, adding any errors in the blocks
+/// nested in it to `errors`.
+fn next_statement(
+    first: Lexeme,
+    lexemes: &mut Lexemes,
+    sources: &Sources,
+    errors: &mut Vec<Diagnostic>,
+) -> Result<Spanned<Statement>, Diagnostic> {
+    let text = sources.text(first.span.clone());
+    // the statement ends at the last lexeme of it from the same place
+    let mut end = first.span.end;
+    let mut extend = |lexeme: &Lexeme| {
+        if lexeme.expanded_from == first.expanded_from && lexeme.span.start >= first.span.start {
+            end = lexeme.span.end;
+        }
+    };
+    let mnemonic = match first.token {
+        Token::Add => Mnemonic::Add,
+        Token::Subtract => Mnemonic::Subtract,
+        Token::Multiply => Mnemonic::Multiply,
+        Token::Divide => Mnemonic::Divide,
+        Token::Modulo => Mnemonic::Modulo,
+        Token::BitwiseNegate => Mnemonic::BitwiseNegate,
+        Token::LogicalNot => Mnemonic::LogicalNot,
+        Token::BitwiseAnd => Mnemonic::BitwiseAnd,
+        Token::BitwiseOr => Mnemonic::BitwiseOr,
+        Token::BitwiseXor => Mnemonic::BitwiseXor,
+        Token::Equal => Mnemonic::Equal,
+        Token::Less => Mnemonic::Less,
+        Token::Greater => Mnemonic::Greater,
+        Token::LoopStart | Token::LoopContinue => {
+            let (condition, text) = expect(lexemes, sources, "loop condition", &first)?;
+            extend(&condition);
+            let open = match first.token {
+                Token::LoopStart => lexemes.next_if(|lexeme| lexeme.token == Token::OpenBrace),
+                _ => None,
+            };
+            if let Some(open) = open {
+                let condition = match condition.token {
+                    Token::LoopConditionNotZero => Condition::NonZero,
+                    Token::LoopConditionZero => Condition::Zero,
+                    _ => {
+                        return Err(
+                            condition.error(format!("Expected loop condition but found {:?}", text))
+                        )
+                    },
+                };
+                let (body, close) = block(&open, lexemes, sources, errors)?;
+                extend(&close);
+                return Ok(Spanned {
+                    node: Statement::While { condition, body },
+                    span: first.span.start..end,
+                    expanded_from: first.expanded_from,
+                });
+            }
+            match (&first.token, &condition.token) {
+                (Token::LoopStart, Token::LoopConditionNotZero) => Mnemonic::WhileNonZero,
+                (Token::LoopStart, Token::LoopConditionZero) => Mnemonic::WhileZero,
+                (_, Token::LoopConditionNotZero) => Mnemonic::NextNonZero,
+                (_, Token::LoopConditionZero) => Mnemonic::NextZero,
+                _ => {
+                    return Err(
+                        condition.error(format!("Expected loop condition but found {:?}", text))
+                    )
+                },
+            }
+        },
+        Token::LoopConditionNotZero | Token::LoopConditionZero => {
+            return Err(first.error(format!(
+                "Found unexpected loop condition {:?} while searching for a mnemonic",
+                text
+            )))
+        },
+        Token::Duplicate => Mnemonic::Duplicate,
+        Token::Drop => Mnemonic::Drop,
+        Token::Swap => {
+            let (kind, text) = expect(lexemes, sources, "swap type", &first)?;
+            extend(&kind);
+            match kind.token {
+                Token::SwapValues => Mnemonic::SwapValues,
+                Token::SwapStacks => Mnemonic::SwapStacks,
+                _ => return Err(kind.error(format!("Expected swap type but found {:?}", text))),
+            }
+        },
+        Token::SwapValues | Token::SwapStacks => {
+            return Err(first.error(format!(
+                "Found unexpected swap type {:?} while searching for a mnemonic",
+                text
+            )))
+        },
+        Token::Conditional => {
+            match lexemes.next_if(|lexeme| matches!(lexeme.token, Token::Name(_))) {
+                Some(lexeme) => {
+                    extend(&lexeme);
+                    let name = match lexeme.token {
+                        Token::Name(name) => name,
+                        _ => unreachable!(),
+                    };
+                    return Ok(Spanned {
+                        node: Statement::JumpIfZero(name),
+                        span: first.span.start..end,
+                        expanded_from: first.expanded_from,
+                    });
+                },
+                None => Mnemonic::Conditional,
+            }
+        },
+        Token::Print => Mnemonic::Print,
+        Token::Input => Mnemonic::Input,
+        Token::PrintNumber => Mnemonic::PrintNumber,
+        Token::InputNumber => Mnemonic::InputNumber,
+        Token::MoveToCell => Mnemonic::MoveToCell,
+        Token::LoadFromCell => Mnemonic::LoadFromCell,
+        Token::HeapLoad => Mnemonic::HeapLoad,
+        Token::HeapStore => Mnemonic::HeapStore,
+        Token::Return => Mnemonic::Return,
+        _ => {
+            let node = statement(&first, text, lexemes, sources, errors, &mut extend)?;
+            return Ok(Spanned {
+                node,
+                span: first.span.start..end,
+                expanded_from: first.expanded_from,
+            });
+        },
+    };
+    let mut operands = vec![];
+    let count = match mnemonic.kind() {
+        Kind::Nullary => 0,
+        Kind::Unary => 1,
+        Kind::Symmetric | Kind::Asymmetric => 2,
+    };
+    while operands.len() < count {
+        match operand(lexemes) {
+            Some(operand) => {
+                if operand.expanded_from == first.expanded_from {
+                    end = operand.span.end;
+                }
+                operands.push(operand);
+            },
+            None => break,
+        }
+    }
+    Ok(Spanned {
+        node: Statement::Op { mnemonic, operands },
+        span: first.span.start..end,
+        expanded_from: first.expanded_from,
+    })
+}
+
+/// Parse a statement which isn't a [`Mnemonic`], starting with `first`,
+/// passing each lexeme after it that it takes to `extend`.
+fn statement(
+    first: &Lexeme,
+    text: &str,
+    lexemes: &mut Lexemes,
+    sources: &Sources,
+    errors: &mut Vec<Diagnostic>,
+    extend: &mut impl FnMut(&Lexeme),
+) -> Result<Statement, Diagnostic> {
+    let unexpected = |what: String| {
+        Err(first.error(format!("Found unexpected {} while searching for a mnemonic", what)))
+    };
+    let statement = match &first.token {
+        Token::Jump => {
+            let (distance, text) = expect(lexemes, sources, "jump distance or label", first)?;
+            extend(&distance);
+            match &distance.token {
+                Token::JumpDistance(distance) => Statement::Skip(*distance),
+                Token::Name(name) => Statement::Jump(name.clone()),
+                _ => {
+                    return Err(distance
+                        .error(format!("Expected jump distance or label but found {:?}", text)))
+                },
+            }
+        },
+        Token::JumpDistance(distance) => return unexpected(format!("jump distance {}", distance)),
+        Token::Number(number) => return unexpected(format!("number {}", number)),
+        Token::Push => {
+            let (value, text) = expect(lexemes, sources, "literal", first)?;
+            extend(&value);
+            match &value.token {
+                Token::Literal(literal) => Statement::Push(Value::Literal(literal.clone())),
+                Token::StringLiteral(string) => {
+                    Statement::Push(Value::String(unescape(&value, string)?))
+                },
+                _ => return Err(value.error(format!("Expected literal but found {:?}", text))),
+            }
+        },
+        Token::Literal(literal) => return unexpected(format!("literal {}", literal)),
+        Token::StringLiteral(string) => {
+            return unexpected(format!("string literal {:?}", string))
+        },
+        Token::Target(target) => return unexpected(format!("target {:?}", target.name())),
+        Token::RawCode => {
+            let (code, text) = expect(lexemes, sources, "raw code", first)?;
+            extend(&code);
+            match &code.token {
+                Token::StringLiteral(string) => {
+                    Statement::Raw(String::from_utf8(unescape(&code, string)?).map_err(|_| {
+                        code.error("Raw code can't escape bytes which aren't ASCII")
+                    })?)
+                },
+                _ => return Err(code.error(format!("Expected raw code but found {:?}", text))),
+            }
+        },
+        Token::Dialect => {
+            let mut extensions = vec![];
+            while let Some(lexeme) =
+                lexemes.next_if(|lexeme| matches!(lexeme.token, Token::ExtensionName(_)))
+            {
+                extend(&lexeme);
+                if let Token::ExtensionName(extension) = lexeme.token {
+                    extensions.push(extension);
+                }
+            }
+            if extensions.is_empty() {
+                return Err(first.error("Expected extension name after dialect"));
+            }
+            Statement::Dialect(extensions)
+        },
+        Token::ExtensionName(extension) => {
+            return unexpected(format!("extension name {:?}", extension.name()))
+        },
+        Token::Define | Token::Call => {
+            let (name, text) = expect(lexemes, sources, "subroutine name", first)?;
+            extend(&name);
+            let name = match &name.token {
+                Token::Name(name) => name.clone(),
+                _ => {
+                    return Err(
+                        name.error(format!("Expected subroutine name but found {:?}", text))
+                    )
+                },
+            };
+            if first.token == Token::Define {
+                Statement::Define(name)
+            } else {
+                Statement::Call(name)
+            }
+        },
+        Token::Label(name) => Statement::Label(name.clone()),
+        Token::If => {
+            let open = opening(lexemes, sources, first)?;
+            let (then, close) = block(&open, lexemes, sources, errors)?;
+            extend(&close);
+            let otherwise = match lexemes.next_if(|lexeme| lexeme.token == Token::Else) {
+                Some(otherwise) => {
+                    let open = opening(lexemes, sources, &otherwise)?;
+                    let (otherwise, close) = block(&open, lexemes, sources, errors)?;
+                    extend(&close);
+                    Some(otherwise)
+                },
+                None => None,
+            };
+            Statement::If { then, otherwise }
+        },
+        Token::Repeat => {
+            let (lexeme, text) = expect(lexemes, sources, "repeat count", first)?;
+            extend(&lexeme);
+            // a number, or a numeric literal (but not a character)
+            let count = match &lexeme.token {
+                Token::JumpDistance(count) => Some(*count as usize),
+                Token::Number(count) => Some(*count),
+                Token::Literal(literal) if !text.starts_with('\'') => Some(literal_value(literal)),
+                _ => None,
+            };
+            let count = match count {
+                Some(count) => count,
+                None => {
+                    let error = lexeme.error(format!("Expected repeat count but found {:?}", text));
+                    if lexeme.token == Token::OpenBrace {
+                        block(&lexeme, lexemes, sources, errors)?;
+                    } else {
+                        skip_block(lexemes, sources, errors)?;
+                    }
+                    return Err(error);
+                },
+            };
+            let open = opening(lexemes, sources, &lexeme)?;
+            let (body, close) = block(&open, lexemes, sources, errors)?;
+            extend(&close);
+            if count > MAX_REPEAT {
+                return Err(lexeme.error(format!(
+                    "Repeat count {} is too large (at most {})",
+                    count, MAX_REPEAT
+                )));
+            }
+            Statement::Repeat { count, body }
+        },
+        Token::OpenBrace => {
+            block(first, lexemes, sources, errors)?;
+            return Err(first.error("Found `{` without `if`, `while` or `repeat` before it"));
+        },
+        Token::CloseBrace => return Err(first.error("Found `}` without a block to close")),
+        Token::Else => {
+            skip_block(lexemes, sources, errors)?;
+            return Err(first.error("Found `else` without an `if` before it"));
+        },
+        _ => return Err(invalid(text, first.span.clone()).within(&first.expanded_from)),
+    };
+    Ok(statement)
+}
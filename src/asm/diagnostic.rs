@@ -0,0 +1,96 @@
+//! Errors in assembly source, and showing where they are.
+
+use std::fmt;
+
+use super::ast::{Call, Span, Spanned};
+use super::source::Sources;
+
+/// An error in assembly source, with the span of source it's about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+    /// The macro calls the code with the error was expanded from, innermost
+    /// first.
+    pub expanded_from: Vec<Call>,
+}
+
+/// Show `path:line:column: message`, followed by the line `span` starts on
+/// with the span underlined (up to the end of that line, for spans covering
+/// several).
+fn excerpt(sources: &Sources, span: &Span, message: &str) -> String {
+    let (file, line, column) = sources.position(span.start);
+    let start = span.start - file.start;
+    let line_start = file.text[..start].rfind('\n').map_or(0, |i| i + 1);
+    let text = file.text[line_start..].lines().next().unwrap_or("");
+    let end = (span.end - file.start).clamp(start, line_start + text.len());
+    // keep tabs, so that the underline lines up however they're shown
+    let indent: String = text
+        .chars()
+        .take(column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let width = file.text[start..end].chars().count().max(1);
+    let margin = " ".repeat(line.to_string().len());
+    format!(
+        "{}:{}:{}: {}\n{} |\n{} | {}\n{} | {}{}\n",
+        file.path,
+        line,
+        column,
+        message,
+        margin,
+        line,
+        text,
+        margin,
+        indent,
+        "^".repeat(width)
+    )
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic {
+            message: message.into(),
+            span,
+            expanded_from: vec![],
+        }
+    }
+
+    /// An error about `spanned`, wherever it was expanded from.
+    pub fn at<T>(message: impl Into<String>, spanned: &Spanned<T>) -> Diagnostic {
+        Diagnostic::new(message, spanned.span.clone()).within(&spanned.expanded_from)
+    }
+
+    /// The same error, in code expanded from the macro calls `expanded_from`.
+    pub fn within(self, expanded_from: &[Call]) -> Diagnostic {
+        Diagnostic {
+            expanded_from: expanded_from.to_vec(),
+            ..self
+        }
+    }
+
+    /// The path of the file the span starts in, and the line and column it
+    /// starts at, both counting from 1, with columns counted in characters.
+    pub fn position<'a>(&self, sources: &'a Sources) -> (&'a str, usize, usize) {
+        let (file, line, column) = sources.position(self.span.start);
+        (&file.path, line, column)
+    }
+
+    /// Show the error as `path:line:column: message`, followed by the line
+    /// it's on with the span underlined, and then each macro call it was
+    /// expanded from in the same way.
+    pub fn render(&self, sources: &Sources) -> String {
+        let mut out = excerpt(sources, &self.span, &self.message);
+        for call in &self.expanded_from {
+            let note = format!("note: in this expansion of {:?}", call.name);
+            out.push_str(&excerpt(sources, &call.span, &note));
+        }
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
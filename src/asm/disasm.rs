@@ -0,0 +1,212 @@
+//! Turning `.cel` programs back into assembly.
+
+use crate::program::{BinaryOp, ExtensionOp, Op, Program};
+
+/// How the ASCII `byte` is written in a string literal.
+fn escape(byte: u8) -> String {
+    match byte {
+        b'\\' => "\\\\".to_string(),
+        b'\n' => "\\n".to_string(),
+        b'\t' => "\\t".to_string(),
+        b'\r' => "\\r".to_string(),
+        _ if byte.is_ascii_control() => format!("\\x{:02x}", byte),
+        _ => (byte as char).to_string(),
+    }
+}
+
+/// The assembly for `op`, if there is some which assembles to exactly `bytes`
+/// (the encoding `op` was decoded from).
+fn mnemonic(op: &Op, bytes: &[u8]) -> Option<String> {
+    let mnemonic = match op {
+        Op::Push(_) => {
+            return match bytes[0] {
+                // the assembler pushes brackets by value
+                b'\'' if bytes[1].is_ascii_graphic() && !b"[]()".contains(&bytes[1])
+                    || bytes[1] == b' ' =>
+                {
+                    Some(format!("push '{}'", bytes[1] as char))
+                },
+                b'#' => Some(format!("push ${}", std::str::from_utf8(&bytes[1..]).ok()?)),
+                // the assembler reverses strings, so that they're printed in
+                // the order they're written, and pushes brackets and bytes
+                // which aren't ASCII on their own
+                b'"' => {
+                    let text = &bytes[1..bytes.len() - 1];
+                    if !text.is_ascii() || text.iter().any(|byte| b"[]()".contains(byte)) {
+                        return None;
+                    }
+                    let escaped: String = text.iter().rev().map(|&byte| escape(byte)).collect();
+                    Some(format!("push \"{}\"", escaped))
+                },
+                _ => None,
+            };
+        },
+        Op::Skip(distance) => return Some(format!("jmp {}", distance)),
+        Op::JumpIf { .. } => {
+            match bytes[0] {
+                b'?' => "jz",
+                b'[' => "while nonzero",
+                b']' => "next nonzero",
+                b'(' => "while zero",
+                _ => "next zero",
+            }
+        },
+        Op::Halt => return None,
+        Op::Duplicate => "dup",
+        Op::Drop => "drop",
+        Op::Store => "st",
+        Op::Load => "ld",
+        Op::Swap => "swap values",
+        Op::SwapStacks => "swap stacks",
+        Op::Not => "not",
+        Op::Invert => "inv",
+        Op::Binary(op) => {
+            match op {
+                BinaryOp::Less => "lt",
+                BinaryOp::Greater => "gt",
+                BinaryOp::Equal => "eq",
+                BinaryOp::Add => "add",
+                BinaryOp::Subtract => "sub",
+                BinaryOp::Multiply => "mul",
+                BinaryOp::Divide => "div",
+                BinaryOp::Modulo => "mod",
+                BinaryOp::Xor => "xor",
+                BinaryOp::And => "and",
+                BinaryOp::Or => "or",
+            }
+        },
+        Op::Print => "out",
+        Op::Input => "in",
+        Op::Extension(ExtensionOp::PrintNumber) => "outnum",
+        Op::Extension(ExtensionOp::InputNumber) => "innum",
+        // only letters can be written as subroutine names
+        Op::Extension(ExtensionOp::Define(label)) if label.is_ascii_alphabetic() => {
+            return Some(format!("def {}", *label as char));
+        },
+        Op::Extension(ExtensionOp::Call { label, .. }) if label.is_ascii_alphabetic() => {
+            return Some(format!("call {}", *label as char));
+        },
+        Op::Extension(ExtensionOp::Define(_) | ExtensionOp::Call { .. }) => return None,
+        Op::Extension(ExtensionOp::Return) => "ret",
+        Op::Extension(ExtensionOp::HeapLoad) => "peek",
+        Op::Extension(ExtensionOp::HeapStore) => "poke",
+    };
+    Some(mnemonic.to_string())
+}
+
+/// Wrap bytes with no mnemonic (such as `.` or invalid instructions) in a
+/// `raw` block.
+fn raw(bytes: &[u8], start: usize) -> Result<String, String> {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Ok(format!("raw \"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))),
+        Err(error) => {
+            let position = error.valid_up_to();
+            Err(format!(
+                "Byte {:#04x} at offset {} can't be written in assembly",
+                bytes[position],
+                start + position
+            ))
+        },
+    }
+}
+
+/// Disassemble `program` into source which assembles back into exactly the
+/// same tape, one instruction per line. The tape is read from start to end, so
+/// bytes which are only reached by jumping into the middle of an instruction
+/// (such as a string) are shown as part of that instruction. If `offsets` is
+/// set, each line ends with a comment giving the offset of its first byte
+/// (counting from the end of the header, if the program has one).
+pub fn disassemble(program: &Program, offsets: bool) -> Result<String, String> {
+    let mut lines = vec![];
+    let mut unknown_start = 0;
+    let mut offset = 0;
+    while offset < program.tape.len() {
+        let decoded = program.instructions[offset].as_ref().ok().and_then(|instruction| {
+            // skipped bytes are instructions in their own right
+            let end = match instruction.op {
+                Op::Skip(_) => offset + 1,
+                _ => instruction.next,
+            };
+            Some((mnemonic(&instruction.op, &program.tape[offset..end])?, end))
+        });
+        if let Some((text, end)) = decoded {
+            if unknown_start < offset {
+                lines.push((
+                    unknown_start,
+                    raw(&program.tape[unknown_start..offset], unknown_start)?,
+                ));
+            }
+            lines.push((offset, text));
+            offset = end;
+            unknown_start = end;
+        } else {
+            offset += 1;
+        }
+    }
+    if unknown_start < program.tape.len() {
+        lines.push((
+            unknown_start,
+            raw(&program.tape[unknown_start..], unknown_start)?,
+        ));
+    }
+
+    // the assembler stops the program before its first `def` if the code
+    // before it could run into it, so a tape which does run into it keeps the
+    // `D` (and the calls to it, which would otherwise be undefined) as raw code
+    let mut defined = vec![];
+    for i in 0..lines.len() {
+        let label = match lines[i].1.strip_prefix("def ") {
+            Some(label) => label.to_string(),
+            None => continue,
+        };
+        let ends = i > 0 && (lines[i - 1].1 == "ret" || lines[i - 1].1.ends_with(".\""));
+        if defined.is_empty() && !ends {
+            lines[i].1 = format!("raw \"D{}\"", label);
+        } else {
+            defined.push(label);
+        }
+    }
+    for (_, text) in &mut lines {
+        if let Some(label) = text.strip_prefix("call ") {
+            if !defined.iter().any(|defined| defined == label) {
+                *text = format!("raw \"C{}\"", label);
+            }
+        }
+    }
+
+    // indent loop bodies, as the formatter would
+    let mut depth: usize = 0;
+    let lines: Vec<(usize, String)> = lines
+        .into_iter()
+        .map(|(offset, text)| {
+            if text.starts_with("next ") {
+                depth = depth.saturating_sub(1);
+            }
+            let line = format!("{}{}", "    ".repeat(depth), text);
+            if text.starts_with("while ") {
+                depth += 1;
+            }
+            (offset, line)
+        })
+        .collect();
+    let width = lines
+        .iter()
+        .filter(|(_, text)| !text.contains('\n'))
+        .map(|(_, text)| text.chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut out = String::new();
+    if !program.dialect.is_classic() {
+        let names: Vec<_> = program.dialect.extensions().iter().map(|e| e.name()).collect();
+        out.push_str(&format!("dialect {}\n", names.join(" ")));
+    }
+    for (offset, text) in lines {
+        if offsets {
+            out.push_str(&format!("{:<width$}  ; {}\n", text, offset, width = width));
+        } else {
+            out.push_str(&text);
+            out.push('\n');
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,171 @@
+//! Canonical formatting of assembly source.
+
+use logos::Logos;
+
+use super::ast::invalid;
+use super::diagnostic::Diagnostic;
+use super::Token;
+
+/// Whether `token` belongs to the mnemonic before it, rather than starting a
+/// new line.
+fn is_operand(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::JumpDistance(_)
+            | Token::Number(_)
+            | Token::Literal(_)
+            | Token::StringLiteral(_)
+            | Token::Target(_)
+            | Token::SwapValues
+            | Token::SwapStacks
+            | Token::LoopConditionNotZero
+            | Token::LoopConditionZero
+            | Token::ExtensionName(_)
+            | Token::Name(_)
+    )
+}
+
+fn finish_line(out: &mut String, line: &str, depth: usize) {
+    if !line.is_empty() {
+        out.push_str(&"    ".repeat(depth));
+        out.push_str(line);
+        out.push('\n');
+    }
+}
+
+/// Format assembly source: each mnemonic goes on its own line with its
+/// operands, loop bodies are indented by four spaces, comments stay on their
+/// own line or after their mnemonic (and are rewritten to start with `;`), and
+/// runs of blank lines are collapsed into one. Macro bodies are indented like
+/// loop bodies, and the parameters of a macro and the arguments of a call stay
+/// on the line they start on. Blocks are indented too, with `{` at the end of
+/// the line opening them and `}` on its own line (or followed by `else {`).
+pub fn format(source: &str) -> Result<String, Vec<Diagnostic>> {
+    let mut errors = vec![];
+    let mut out = String::new();
+    let mut line = String::new();
+    let mut commented = false;
+    let mut line_depth = 0;
+    let mut depth: usize = 0;
+    let mut previous_end = 0;
+    let mut lexer = Token::lexer(source);
+    let mut previous = None;
+    // how many parentheses are open, and whether they're a macro's parameters
+    let mut parens: usize = 0;
+    let mut parameters = false;
+    // whether the line ends with a parenthesis or a brace, so only a comment
+    // can follow
+    let mut closed = false;
+    // the token the current line starts with
+    let mut first = None;
+    let mut in_macro = false;
+    while let Some(token) = lexer.next() {
+        let span = lexer.span();
+        let newlines = source[previous_end..span.start].matches('\n').count();
+        previous_end = span.end;
+        let mut text = lexer.slice().trim_end().to_string();
+        // comments starting with `#` are converted to the current syntax
+        let token = match token {
+            Token::LegacyComment => {
+                text.replace_range(..1, ";");
+                Token::Comment
+            },
+            token => token,
+        };
+        let before = previous.replace(token.clone());
+        let named = matches!(
+            before,
+            Some(Token::Define | Token::Call | Token::Jump | Token::Conditional | Token::Macro)
+        );
+        let call = matches!(token, Token::Name(_))
+            && !named
+            && matches!(lexer.clone().next(), Some(Token::OpenParen));
+        if token == Token::Error {
+            errors.push(invalid(&text, span));
+            continue;
+        }
+        if parens > 0 || token == Token::OpenParen {
+            match token {
+                Token::OpenParen => parens += 1,
+                Token::CloseParen => parens -= 1,
+                _ => {},
+            }
+            if !matches!(token, Token::OpenParen | Token::CloseParen | Token::Comma)
+                && before != Some(Token::OpenParen)
+            {
+                line.push(' ');
+            }
+            line.push_str(&text);
+            if parens == 0 {
+                closed = true;
+                if parameters {
+                    parameters = false;
+                    depth += 1;
+                }
+            }
+            continue;
+        }
+        match token {
+            // only subroutines, labels, macros and their parameters have names
+            Token::Name(_) if !named && !call && !in_macro => {
+                errors.push(invalid(&text, span));
+                continue;
+            },
+            Token::Comment if newlines == 0 && !line.is_empty() && !commented => {
+                line.push_str("  ");
+                line.push_str(&text);
+                commented = true;
+                continue;
+            },
+            _ if is_operand(&token) && !call && !line.is_empty() && !commented && !closed => {
+                line.push(' ');
+                line.push_str(&text);
+                continue;
+            },
+            // `while` has already indented what follows it
+            Token::OpenBrace if !line.is_empty() && !commented && !closed => {
+                if first != Some(Token::LoopStart) {
+                    depth += 1;
+                }
+                line.push_str(" {");
+                closed = true;
+                continue;
+            },
+            Token::Else if before == Some(Token::CloseBrace) && !commented => {
+                line.push_str(" else");
+                continue;
+            },
+            _ => {},
+        }
+        finish_line(&mut out, &line, line_depth);
+        if newlines > 1 && !out.is_empty() {
+            out.push('\n');
+        }
+        match token {
+            Token::LoopContinue | Token::CloseBrace => depth = depth.saturating_sub(1),
+            Token::EndMacro => {
+                depth = depth.saturating_sub(1);
+                in_macro = false;
+            },
+            Token::Macro => {
+                parameters = true;
+                in_macro = true;
+            },
+            _ => {},
+        }
+        line_depth = depth;
+        if matches!(token, Token::LoopStart | Token::OpenBrace) {
+            depth += 1;
+        }
+        line = text;
+        commented = token == Token::Comment;
+        closed = token == Token::OpenBrace;
+        first = Some(token);
+    }
+    finish_line(&mut out, &line, line_depth);
+    if errors.is_empty() {
+        Ok(out)
+    } else {
+        Err(errors)
+    }
+}
@@ -0,0 +1,397 @@
+//! Macros, which are expanded before assembly is parsed into statements.
+//!
+//! A macro is defined with `macro name(a, b)`, followed by its body and
+//! `endmacro`, and called with `name(x, y)`. The parameters in the body are
+//! replaced by the tokens passed for them, and labels defined in the body are
+//! renamed in each expansion, so that a macro which uses a label can be called
+//! more than once.
+
+use std::collections::{HashMap, HashSet};
+use std::iter::Peekable;
+use std::vec::IntoIter;
+
+use logos::Logos;
+
+use super::ast::{Call, Span};
+use super::diagnostic::Diagnostic;
+use super::source::Sources;
+use super::Token;
+
+/// A token, where it is, and the macro calls it was expanded from (innermost
+/// first).
+#[derive(Debug, Clone)]
+pub(super) struct Lexeme {
+    pub token: Token,
+    pub span: Span,
+    pub expanded_from: Vec<Call>,
+}
+
+impl Lexeme {
+    pub fn error(&self, message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(message, self.span.clone()).within(&self.expanded_from)
+    }
+}
+
+type Lexemes = Peekable<IntoIter<Lexeme>>;
+
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<Lexeme>,
+    /// The labels defined in the body.
+    labels: HashSet<String>,
+}
+
+/// Split `files` into tokens (leaving out comments), one after another, with
+/// the files each includes read in its place, every macro definition taken out
+/// and every call of one expanded. Macros defined in any of the files can be
+/// called in all of them, and files are only read once.
+pub(super) fn expand(
+    sources: &mut Sources,
+    files: &[usize],
+) -> Result<Vec<Lexeme>, Vec<Diagnostic>> {
+    let mut expander = Expander {
+        macros: HashMap::new(),
+        read: HashSet::new(),
+        calling: vec![],
+        expansions: 0,
+        errors: vec![],
+    };
+    let mut code = vec![];
+    for &file in files {
+        if !expander.read.contains(&file) {
+            expander.read(sources, file, &mut vec![], &mut code);
+        }
+    }
+    let expanded = expander.expand(code);
+    if expander.errors.is_empty() {
+        Ok(expanded)
+    } else {
+        Err(expander.errors)
+    }
+}
+
+/// Split `file` into tokens, leaving out comments.
+fn lex(sources: &Sources, file: usize) -> Lexemes {
+    let file = sources.file(file);
+    Token::lexer(&file.text)
+        .spanned()
+        .filter(|(token, _)| !matches!(token, Token::Comment | Token::LegacyComment))
+        .map(|(token, span)| {
+            Lexeme {
+                token,
+                span: file.start + span.start..file.start + span.end,
+                expanded_from: vec![],
+            }
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .peekable()
+}
+
+/// The next lexeme, or an error saying that `expected` was, at `at` (the
+/// lexeme it should have followed).
+fn next(lexemes: &mut Lexemes, expected: &str, at: &Lexeme) -> Result<Lexeme, Diagnostic> {
+    lexemes
+        .next()
+        .ok_or_else(|| at.error(format!("Expected {} but found EOF", expected)))
+}
+
+/// Read the definition of a macro, after the `macro` keyword.
+fn define(
+    keyword: &Lexeme,
+    lexemes: &mut Lexemes,
+    sources: &Sources,
+) -> Result<(String, Macro), Diagnostic> {
+    let lexeme = next(lexemes, "macro name", keyword)?;
+    let name = match lexeme.token {
+        Token::Name(ref name) => name.clone(),
+        _ => {
+            return Err(lexeme.error(format!(
+                "Expected macro name but found {:?}",
+                sources.text(lexeme.span.clone())
+            )))
+        },
+    };
+    let open = next(lexemes, "`(`", &lexeme)?;
+    if open.token != Token::OpenParen {
+        return Err(open.error(format!("Expected `(` but found {:?}", sources.text(open.span.clone()))));
+    }
+    let mut parameters = vec![];
+    let mut lexeme = next(lexemes, "parameter name or `)`", &open)?;
+    if lexeme.token != Token::CloseParen {
+        loop {
+            match &lexeme.token {
+                Token::Name(parameter) if parameters.contains(parameter) => {
+                    return Err(lexeme.error(format!("Parameter {:?} is given twice", parameter)))
+                },
+                Token::Name(parameter) => parameters.push(parameter.clone()),
+                _ => {
+                    return Err(lexeme.error(format!(
+                        "Expected parameter name but found {:?}",
+                        sources.text(lexeme.span.clone())
+                    )))
+                },
+            }
+            let after = next(lexemes, "`,` or `)`", &lexeme)?;
+            match after.token {
+                Token::Comma => lexeme = next(lexemes, "parameter name", &after)?,
+                Token::CloseParen => break,
+                _ => {
+                    return Err(after.error(format!(
+                        "Expected `,` or `)` but found {:?}",
+                        sources.text(after.span.clone())
+                    )))
+                },
+            }
+        }
+    }
+    let mut body = vec![];
+    loop {
+        let lexeme = lexemes.next().ok_or_else(|| {
+            keyword.error(format!("Macro {:?} is never closed with endmacro", name))
+        })?;
+        match lexeme.token {
+            Token::EndMacro => break,
+            Token::Macro => return Err(lexeme.error("Macros can't be defined inside macros")),
+            Token::Include => return Err(lexeme.error("Files can't be included inside macros")),
+            _ => body.push(lexeme),
+        }
+    }
+    let labels = body
+        .iter()
+        .filter_map(|lexeme| {
+            match &lexeme.token {
+                Token::Label(label) => Some(label.clone()),
+                _ => None,
+            }
+        })
+        .collect();
+    Ok((
+        name,
+        Macro {
+            parameters,
+            body,
+            labels,
+        },
+    ))
+}
+
+struct Expander {
+    macros: HashMap<String, Macro>,
+    /// The files which have been read.
+    read: HashSet<usize>,
+    /// The macros being expanded, outermost first.
+    calling: Vec<String>,
+    /// How many expansions there have been, which numbers their labels.
+    expansions: usize,
+    errors: Vec<Diagnostic>,
+}
+
+impl Expander {
+    /// Read `file`, taking out the macros it defines and adding the rest of it
+    /// to `code`. `including` is the files being read, outermost first.
+    fn read(
+        &mut self,
+        sources: &mut Sources,
+        file: usize,
+        including: &mut Vec<usize>,
+        code: &mut Vec<Lexeme>,
+    ) {
+        self.read.insert(file);
+        including.push(file);
+        let mut lexemes = lex(sources, file);
+        while let Some(lexeme) = lexemes.next() {
+            match lexeme.token {
+                Token::Macro => {
+                    match define(&lexeme, &mut lexemes, sources) {
+                        Ok((name, _)) if self.macros.contains_key(&name) => {
+                            self.errors
+                                .push(lexeme.error(format!("Macro {:?} is defined twice", name)));
+                        },
+                        Ok((name, definition)) => {
+                            self.macros.insert(name, definition);
+                        },
+                        Err(error) => {
+                            self.errors.push(error);
+                            // skip the rest of the definition
+                            while lexemes.next_if(|lexeme| lexeme.token != Token::EndMacro).is_some() {}
+                            lexemes.next();
+                        },
+                    }
+                },
+                Token::EndMacro => {
+                    self.errors.push(lexeme.error("Found endmacro outside of a macro"));
+                },
+                Token::Include => {
+                    if let Err(error) = self.include(sources, &lexeme, &mut lexemes, including, code) {
+                        self.errors.push(error);
+                    }
+                },
+                _ => code.push(lexeme),
+            }
+        }
+        including.pop();
+    }
+
+    /// Read the file included by the `include` at `keyword`, unless it's
+    /// already been read.
+    fn include(
+        &mut self,
+        sources: &mut Sources,
+        keyword: &Lexeme,
+        lexemes: &mut Lexemes,
+        including: &mut Vec<usize>,
+        code: &mut Vec<Lexeme>,
+    ) -> Result<(), Diagnostic> {
+        let lexeme = next(lexemes, "file name", keyword)?;
+        let name = match &lexeme.token {
+            Token::StringLiteral(name) => name.trim_matches('"'),
+            _ => {
+                return Err(lexeme.error(format!(
+                    "Expected file name but found {:?}",
+                    sources.text(lexeme.span.clone())
+                )))
+            },
+        };
+        let mut at = keyword.clone();
+        at.span.end = lexeme.span.end;
+        let file = sources.include(name, *including.last().unwrap()).map_err(|e| at.error(e))?;
+        if let Some(start) = including.iter().position(|&including| including == file) {
+            let cycle: Vec<&str> = including[start..]
+                .iter()
+                .chain([&file])
+                .map(|&file| sources.file(file).path.as_str())
+                .collect();
+            return Err(at.error(format!("Files include each other: {}", cycle.join(" -> "))));
+        }
+        // files are only read once, so they can all include what they need
+        if !self.read.contains(&file) {
+            self.read(sources, file, including, code);
+        }
+        Ok(())
+    }
+
+    /// Expand every macro call in `lexemes`.
+    fn expand(&mut self, lexemes: Vec<Lexeme>) -> Vec<Lexeme> {
+        let mut lexemes = lexemes.into_iter().peekable();
+        let mut out = vec![];
+        while let Some(lexeme) = lexemes.next() {
+            let name = match &lexeme.token {
+                Token::Name(name)
+                    if matches!(lexemes.peek(), Some(next) if next.token == Token::OpenParen) =>
+                {
+                    name.clone()
+                },
+                _ => {
+                    out.push(lexeme);
+                    continue;
+                },
+            };
+            lexemes.next();
+            match self.call(&name, &lexeme, &mut lexemes) {
+                Ok(expansion) => out.extend(expansion),
+                Err(error) => self.errors.push(error),
+            }
+        }
+        out
+    }
+
+    /// Expand a call of the macro `name`, reading its arguments (after the
+    /// `(`) from `lexemes`.
+    fn call(
+        &mut self,
+        name: &str,
+        at: &Lexeme,
+        lexemes: &mut Lexemes,
+    ) -> Result<Vec<Lexeme>, Diagnostic> {
+        let mut arguments = vec![vec![]];
+        let mut depth = 0;
+        let close = loop {
+            let lexeme = lexemes
+                .next()
+                .ok_or_else(|| at.error(format!("Expected `)` after the arguments to {:?}", name)))?;
+            match lexeme.token {
+                Token::CloseParen if depth == 0 => break lexeme,
+                Token::Comma if depth == 0 => arguments.push(vec![]),
+                _ => {
+                    match lexeme.token {
+                        Token::OpenParen => depth += 1,
+                        Token::CloseParen => depth -= 1,
+                        _ => {},
+                    }
+                    arguments.last_mut().unwrap().push(lexeme);
+                },
+            }
+        };
+        // the call, if it's all in the same place
+        let mut call = at.clone();
+        if close.expanded_from == at.expanded_from {
+            call.span = at.span.start..close.span.end;
+        }
+        let definition = self
+            .macros
+            .get(name)
+            .ok_or_else(|| call.error(format!("Macro {:?} is not defined", name)))?;
+        if self.calling.iter().any(|calling| calling == name) {
+            return Err(call.error(format!("Macro {:?} calls itself", name)));
+        }
+        if arguments.len() == 1 && arguments[0].is_empty() {
+            arguments.clear();
+        }
+        if arguments.len() != definition.parameters.len() {
+            return Err(call.error(format!(
+                "Macro {:?} takes {} argument{} but was given {}",
+                name,
+                definition.parameters.len(),
+                if definition.parameters.len() == 1 { "" } else { "s" },
+                arguments.len()
+            )));
+        }
+        if arguments.iter().any(Vec::is_empty) {
+            return Err(call.error(format!("Macro {:?} was given an empty argument", name)));
+        }
+        // arguments are expanded where they're written, not in the body
+        let arguments: Vec<Vec<Lexeme>> =
+            arguments.into_iter().map(|argument| self.expand(argument)).collect();
+        let definition = &self.macros[name];
+        let mut expanded_from = vec![Call {
+            name: name.to_string(),
+            span: call.span.clone(),
+        }];
+        expanded_from.extend(call.expanded_from.iter().cloned());
+        self.expansions += 1;
+        let mut body = vec![];
+        let mut previous = None;
+        for lexeme in &definition.body {
+            let token = match &lexeme.token {
+                Token::Name(parameter) if definition.parameters.contains(parameter) => {
+                    let index = definition.parameters.iter().position(|p| p == parameter).unwrap();
+                    body.extend(arguments[index].iter().cloned());
+                    previous = Some(&lexeme.token);
+                    continue;
+                },
+                // labels are renamed, along with the jumps to them
+                Token::Label(label) if definition.labels.contains(label) => {
+                    Token::Label(format!("{}@{}", label, self.expansions))
+                },
+                Token::Name(label)
+                    if definition.labels.contains(label)
+                        && matches!(previous, Some(Token::Jump | Token::Conditional)) =>
+                {
+                    Token::Name(format!("{}@{}", label, self.expansions))
+                },
+                token => token.clone(),
+            };
+            previous = Some(&lexeme.token);
+            body.push(Lexeme {
+                token,
+                span: lexeme.span.clone(),
+                expanded_from: expanded_from.clone(),
+            });
+        }
+        self.calling.push(name.to_string());
+        let body = self.expand(body);
+        self.calling.pop();
+        Ok(body)
+    }
+}
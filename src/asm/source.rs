@@ -0,0 +1,129 @@
+//! The files assembly is read from: the one being assembled, and those it
+//! includes.
+//!
+//! Every file is given its own range of offsets, so that a [`Span`] says which
+//! file it's in as well as where.
+
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use super::ast::Span;
+
+/// The standard library, which is included with `include "std/…"`.
+const LIBRARY: &[(&str, &str)] = &[
+    ("std/io.casm", include_str!("std/io.casm")),
+    ("std/stack.casm", include_str!("std/stack.casm")),
+];
+
+/// What the file at `path` is, however it's reached.
+fn key(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// A file of assembly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct File {
+    /// The path it was read from, as it's shown in errors.
+    pub path: String,
+    pub text: String,
+    /// The offset its first byte is given.
+    pub start: usize,
+    /// What the file is, so that it's only read once however it's found.
+    key: PathBuf,
+}
+
+/// The files read while assembling, and where to look for the files they
+/// include.
+#[derive(Debug, Clone, Default)]
+pub struct Sources {
+    files: Vec<File>,
+    /// The directories included files are looked for in, after the directory
+    /// of the file including them.
+    pub search_paths: Vec<PathBuf>,
+}
+
+impl Sources {
+    pub fn new(search_paths: Vec<PathBuf>) -> Sources {
+        Sources {
+            files: vec![],
+            search_paths,
+        }
+    }
+
+    /// Add a file, returning its index.
+    pub fn add(&mut self, path: impl Into<String>, text: impl Into<String>) -> usize {
+        let path = path.into();
+        self.add_with_key(key(Path::new(&path)), path, text.into())
+    }
+
+    fn add_with_key(&mut self, key: PathBuf, path: String, text: String) -> usize {
+        // leave a gap, so that the end of one file isn't the start of the next
+        let start = self.files.last().map_or(0, |file| file.start + file.text.len() + 1);
+        self.files.push(File {
+            path,
+            text,
+            start,
+            key,
+        });
+        self.files.len() - 1
+    }
+
+    pub fn file(&self, index: usize) -> &File {
+        &self.files[index]
+    }
+
+    /// The index of the file `offset` is in.
+    pub fn index(&self, offset: usize) -> usize {
+        self.files.partition_point(|file| file.start <= offset) - 1
+    }
+
+    /// The text `span` covers.
+    pub fn text(&self, span: Span) -> &str {
+        let file = self.file(self.index(span.start));
+        &file.text[span.start - file.start..span.end - file.start]
+    }
+
+    /// The file `offset` is in, and the line and column it's at, both counting
+    /// from 1, with columns counted in characters.
+    pub fn position(&self, offset: usize) -> (&File, usize, usize) {
+        let file = self.file(self.index(offset));
+        let before = &file.text[..offset - file.start];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        (
+            file,
+            before.matches('\n').count() + 1,
+            before[line_start..].chars().count() + 1,
+        )
+    }
+
+    /// Find the file `name` included by the file `from`, returning its index.
+    /// It's looked for beside `from`, then in each of the search paths, and
+    /// then in the standard library. Files which have already been read aren't
+    /// read again.
+    pub fn include(&mut self, name: &str, from: usize) -> Result<usize, String> {
+        let beside = Path::new(&self.file(from).path).parent().map(Path::to_path_buf);
+        let found = beside
+            .iter()
+            .chain(&self.search_paths)
+            .map(|directory| directory.join(name))
+            .find(|path| path.is_file());
+        let (key, path, text) = match found {
+            Some(path) => {
+                let text = read_to_string(&path)
+                    .map_err(|e| format!("Can't read {}: {}", path.display(), e))?;
+                (key(&path), path.display().to_string(), text)
+            },
+            None => {
+                let &(path, text) = LIBRARY
+                    .iter()
+                    .find(|(path, _)| *path == name)
+                    .ok_or_else(|| format!("Can't find {:?} to include", name))?;
+                (PathBuf::from(path), path.to_string(), text.to_string())
+            },
+        };
+        match self.files.iter().position(|file| file.key == key) {
+            Some(index) => Ok(index),
+            None => Ok(self.add_with_key(key, path, text)),
+        }
+    }
+}
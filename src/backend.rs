@@ -0,0 +1,20 @@
+//! Compilers from `.cel` programs to other languages.
+
+pub mod bf;
+pub mod c;
+pub mod wasm;
+
+use crate::program::{Instruction, Op, Program};
+
+/// The instruction at `offset`, or the error executing it fails with. No
+/// backend supports extensions, so their instructions fail too.
+fn instruction(program: &Program, offset: usize) -> Result<&Instruction, String> {
+    match &program.instructions[offset] {
+        Ok(Instruction {
+            op: Op::Extension(op),
+            ..
+        }) => Err(format!("The {} extension can't be compiled", op.extension())),
+        Ok(instruction) => Ok(instruction),
+        Err(e) => Err(e.clone()),
+    }
+}
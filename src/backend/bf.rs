@@ -0,0 +1,584 @@
+//! Compilation of StackCell to BF.
+//!
+//! The BF tape starts with a fixed set of registers, followed by one flag per
+//! basic block of the program. Control flow is a loop over the block flags:
+//! exactly one flag is set at a time, and running a block clears its own flag
+//! and sets its successor's (or clears `RUN` to halt).
+//!
+//! After the flags come the stacks, in frames of [`FRAME`] cells. Frame 0 is
+//! an all-zero sentinel and frame 1 is "home"; element `i` of the primary
+//! stack is stored in lane A of frame `i + 2` and element `i` of the secondary
+//! stack in lane B. Each lane has a marker cell, which is set for every frame
+//! holding an element (and always for home), so the top of a stack can be
+//! found by walking right from home while the marker is set. The rail cell is
+//! set in every frame which has ever held an element, so the way back to home
+//! is found by walking left while it is set, then stepping right once. Values
+//! are carried along by moving them one frame at a time in the carry cell.
+//!
+//! Arithmetic is done with decrement loops, so programs which rely on large
+//! values are impractically slow with wide cells.
+
+use crate::program::{BinaryOp, Condition, Op, Program};
+use crate::types::CellSize;
+
+// registers
+const RUN: usize = 0;
+const CELL: usize = 1;
+const A: usize = 2;
+const B: usize = 3;
+const C: usize = 4;
+const D: usize = 5;
+const E: usize = 6;
+const F: usize = 7;
+const G: usize = 8;
+const H: usize = 9;
+const I: usize = 10;
+const J: usize = 11;
+const K: usize = 12;
+const L: usize = 13;
+const OK: usize = 14;
+const REGISTERS: usize = 15;
+
+// offsets into each frame
+const RAIL: usize = 0;
+const MARKER_A: usize = 1;
+const VALUE_A: usize = 2;
+const MARKER_B: usize = 3;
+const VALUE_B: usize = 4;
+const CARRY: usize = 5;
+const TEMP: usize = 6;
+const FRAME: usize = 7;
+
+/// The BF to move the data pointer from offset `from` to offset `to`.
+fn shift(from: usize, to: usize) -> String {
+    if to > from {
+        ">".repeat(to - from)
+    } else {
+        "<".repeat(from - to)
+    }
+}
+
+struct Generator {
+    out: String,
+    position: usize,
+    /// The first cell of the home frame.
+    home: usize,
+    /// The number of loops opened to skip the rest of a block after a failed
+    /// division.
+    pending: usize,
+}
+
+impl Generator {
+    fn goto(&mut self, target: usize) {
+        self.out.push_str(&shift(self.position, target));
+        self.position = target;
+    }
+
+    /// Emit code which moves the data pointer, but leaves it where it started.
+    fn emit(&mut self, code: &str) {
+        self.out.push_str(code);
+    }
+
+    fn open(&mut self, register: usize) {
+        self.goto(register);
+        self.emit("[");
+    }
+
+    fn close(&mut self, register: usize) {
+        self.goto(register);
+        self.emit("]");
+    }
+
+    fn clear(&mut self, register: usize) {
+        self.goto(register);
+        self.emit("[-]");
+    }
+
+    fn add(&mut self, register: usize, value: u8) {
+        self.goto(register);
+        if cfg!(not(feature = "u32")) && value > 128 {
+            self.emit(&"-".repeat(256 - value as usize));
+        } else {
+            self.emit(&"+".repeat(value as usize));
+        }
+    }
+
+    fn set(&mut self, register: usize, value: u8) {
+        self.clear(register);
+        self.add(register, value);
+    }
+
+    /// Add `from` to each of `to`, clearing `from`.
+    fn transfer(&mut self, from: usize, to: &[usize]) {
+        self.open(from);
+        self.emit("-");
+        for &target in to {
+            self.goto(target);
+            self.emit("+");
+        }
+        self.close(from);
+    }
+
+    /// Subtract `from` from `to`, clearing `from`.
+    fn subtract(&mut self, from: usize, to: usize) {
+        self.open(from);
+        self.emit("-");
+        self.goto(to);
+        self.emit("-");
+        self.close(from);
+    }
+
+    /// Add `from` to `to`, using `temp` (which must be clear) to restore it.
+    fn copy(&mut self, from: usize, to: usize, temp: usize) {
+        self.transfer(from, &[to, temp]);
+        self.transfer(temp, &[from]);
+    }
+
+    /// Run `then` if `value` is non-zero, or `otherwise` if not, clearing
+    /// `value`. `flag` must be clear.
+    fn branch(
+        &mut self,
+        value: usize,
+        flag: usize,
+        then: impl FnOnce(&mut Self),
+        otherwise: impl FnOnce(&mut Self),
+    ) {
+        self.add(flag, 1);
+        self.open(value);
+        self.clear(value);
+        self.goto(flag);
+        self.emit("-");
+        then(self);
+        self.close(value);
+        self.open(flag);
+        self.emit("-");
+        otherwise(self);
+        self.close(flag);
+    }
+
+    /// Move `register` onto the primary stack, clearing it.
+    fn push(&mut self, register: usize) {
+        let home = self.home;
+        self.transfer(register, &[home + CARRY]);
+        self.goto(home + MARKER_A);
+        // walk right to the first free frame, carrying the value
+        self.emit(&format!(
+            "[{}[-{}+{}]{}{}]",
+            shift(MARKER_A, CARRY),
+            shift(0, FRAME),
+            shift(FRAME, 0),
+            shift(CARRY, MARKER_A),
+            shift(0, FRAME),
+        ));
+        // claim it and store the value
+        self.emit(&format!(
+            "+{}[-]+{}[-{}+{}]{}",
+            shift(MARKER_A, RAIL),
+            shift(RAIL, CARRY),
+            shift(CARRY, VALUE_A),
+            shift(VALUE_A, CARRY),
+            shift(CARRY, RAIL),
+        ));
+        // walk back home along the rail
+        self.emit(&format!("[{}]{}", shift(FRAME, 0), shift(0, FRAME)));
+        self.position = home + RAIL;
+    }
+
+    /// Move the value on top of the primary stack into `register`, which must
+    /// be clear.
+    fn pop(&mut self, register: usize) {
+        let home = self.home;
+        self.goto(home + MARKER_A);
+        // walk right to the top of the stack
+        self.emit(&format!("[{}]{}", shift(0, FRAME), shift(FRAME, 0)));
+        // release it and pick up the value
+        self.emit(&format!(
+            "[-]{}[-{}+{}]{}",
+            shift(MARKER_A, VALUE_A),
+            shift(VALUE_A, CARRY),
+            shift(CARRY, VALUE_A),
+            shift(VALUE_A, RAIL),
+        ));
+        // walk back home along the rail, carrying the value
+        let step_left = format!(
+            "{}[-{}+{}]{}",
+            shift(RAIL, CARRY),
+            shift(FRAME, 0),
+            shift(0, FRAME),
+            shift(CARRY, RAIL),
+        );
+        let step_right = format!(
+            "{}[-{}+{}]{}",
+            shift(RAIL, CARRY),
+            shift(0, FRAME),
+            shift(FRAME, 0),
+            shift(CARRY, RAIL),
+        );
+        self.emit(&format!(
+            "[{}{}]{}{}",
+            step_left,
+            shift(FRAME, 0),
+            step_right,
+            shift(0, FRAME)
+        ));
+        self.position = home + RAIL;
+        // popping from an empty stack releases home, so claim it again
+        self.goto(home + MARKER_A);
+        self.emit("[-]+");
+        self.transfer(home + CARRY, &[register]);
+    }
+
+    fn swap_stacks(&mut self) {
+        let swap = |a: usize, b: usize| {
+            format!(
+                "{}[-{}+{}]{}[-{}+{}]{}[-{}+{}]{}",
+                shift(RAIL, a),
+                shift(a, TEMP),
+                shift(TEMP, a),
+                shift(a, b),
+                shift(b, a),
+                shift(a, b),
+                shift(b, TEMP),
+                shift(TEMP, b),
+                shift(b, TEMP),
+                shift(TEMP, RAIL),
+            )
+        };
+        self.goto(self.home + RAIL);
+        self.emit(&format!(
+            "[{}{}{}]{}[{}]{}",
+            swap(MARKER_A, MARKER_B),
+            swap(VALUE_A, VALUE_B),
+            shift(0, FRAME),
+            shift(FRAME, 0),
+            shift(FRAME, 0),
+            shift(0, FRAME),
+        ));
+    }
+
+    /// Print `message` as the interpreter would on failure, then halt.
+    fn fail(&mut self, message: &str) {
+        for byte in format!("Error: {}\n", message).bytes() {
+            self.set(A, byte);
+            self.emit(".");
+        }
+        self.clear(A);
+        self.clear(RUN);
+    }
+
+    /// Set `lt` and `gt` to whether `left` is less than or greater than
+    /// `right`, clearing both.
+    fn compare(&mut self, left: usize, right: usize, lt: usize, gt: usize) {
+        self.open(left);
+        self.copy(right, G, H);
+        self.branch(
+            G,
+            H,
+            |g| {
+                g.goto(right);
+                g.emit("-");
+                g.goto(left);
+                g.emit("-");
+            },
+            |g| {
+                g.clear(left);
+                g.add(gt, 1);
+            },
+        );
+        self.close(left);
+        self.branch(right, H, |g| g.add(lt, 1), |_| {});
+    }
+
+    /// Set `quotient` and `remainder` to `dividend` divided by `divisor`,
+    /// clearing `dividend`. `divisor` must be non-zero.
+    fn divide(&mut self, dividend: usize, divisor: usize, quotient: usize, remainder: usize) {
+        self.open(dividend);
+        self.emit("-");
+        self.add(remainder, 1);
+        self.copy(divisor, I, J);
+        self.copy(remainder, K, J);
+        self.subtract(K, I);
+        self.branch(I, J, |_| {}, |g| {
+            g.clear(remainder);
+            g.add(quotient, 1);
+        });
+        self.close(dividend);
+    }
+
+    fn bitwise(&mut self, op: BinaryOp, left: usize, right: usize, result: usize) {
+        // E holds the current bit's place value, and L the constant 2
+        self.add(E, 1);
+        self.add(L, 2);
+        for _ in 0..CellSize::BITS {
+            self.divide(left, L, F, G);
+            self.transfer(F, &[left]);
+            self.divide(right, L, F, H);
+            self.transfer(F, &[right]);
+            // G and H are now the bits of left and right, combine them into G
+            self.transfer(H, &[G]);
+            match op {
+                BinaryOp::And => {
+                    self.goto(G);
+                    self.emit("--");
+                    self.branch(G, H, |_| {}, |g| g.add(F, 1));
+                },
+                BinaryOp::Or => self.branch(G, H, |g| g.add(F, 1), |_| {}),
+                _ => {
+                    self.goto(G);
+                    self.emit("-");
+                    self.branch(G, H, |_| {}, |g| g.add(F, 1));
+                },
+            }
+            self.branch(F, H, |g| g.copy(E, result, I), |_| {});
+            self.copy(E, F, I);
+            self.transfer(F, &[E]);
+        }
+        self.clear(E);
+        self.clear(L);
+        self.clear(left);
+        self.clear(right);
+    }
+
+    fn binary(&mut self, op: BinaryOp) {
+        self.pop(A);
+        self.pop(B);
+        match op {
+            BinaryOp::Less | BinaryOp::Greater => {
+                self.compare(A, B, C, D);
+                if op == BinaryOp::Less {
+                    self.clear(D);
+                    self.push(C);
+                } else {
+                    self.clear(C);
+                    self.push(D);
+                }
+            },
+            BinaryOp::Equal => {
+                self.subtract(B, A);
+                self.branch(A, C, |_| {}, |g| g.add(D, 1));
+                self.push(D);
+            },
+            BinaryOp::Add => {
+                self.transfer(B, &[A]);
+                self.push(A);
+            },
+            BinaryOp::Subtract => {
+                self.subtract(B, A);
+                self.push(A);
+            },
+            BinaryOp::Multiply => {
+                self.open(A);
+                self.emit("-");
+                self.copy(B, C, D);
+                self.close(A);
+                self.clear(B);
+                self.push(C);
+            },
+            BinaryOp::Divide | BinaryOp::Modulo => {
+                // dividing by zero halts, skipping the rest of the block
+                self.copy(B, C, D);
+                self.branch(C, D, |g| g.add(OK, 1), |g| g.clear(RUN));
+                self.open(OK);
+                self.clear(OK);
+                self.pending += 1;
+                self.divide(A, B, C, D);
+                self.clear(B);
+                if op == BinaryOp::Divide {
+                    self.clear(D);
+                    self.push(C);
+                } else {
+                    self.clear(C);
+                    self.push(D);
+                }
+            },
+            BinaryOp::Xor | BinaryOp::And | BinaryOp::Or => {
+                self.bitwise(op, A, B, C);
+                self.push(C);
+            },
+        }
+    }
+
+    fn instruction(&mut self, op: &Op) {
+        match op {
+            Op::Push(values) => {
+                for &value in values {
+                    self.add(A, value);
+                    self.push(A);
+                }
+            },
+            Op::Skip(_) | Op::JumpIf { .. } | Op::Halt => {},
+            Op::Duplicate => {
+                self.pop(A);
+                self.copy(A, B, C);
+                self.push(A);
+                self.push(B);
+            },
+            Op::Drop => {
+                self.pop(A);
+                self.clear(A);
+            },
+            Op::Store => {
+                self.clear(CELL);
+                self.pop(CELL);
+            },
+            Op::Load => {
+                self.copy(CELL, A, B);
+                self.push(A);
+            },
+            Op::Swap => {
+                self.pop(A);
+                self.pop(B);
+                self.push(A);
+                self.push(B);
+            },
+            Op::SwapStacks => self.swap_stacks(),
+            Op::Not => {
+                self.pop(A);
+                self.branch(A, C, |_| {}, |g| g.add(B, 1));
+                self.push(B);
+            },
+            Op::Invert => {
+                self.pop(A);
+                self.goto(B);
+                self.emit("-");
+                self.subtract(A, B);
+                self.push(B);
+            },
+            Op::Binary(op) => self.binary(*op),
+            Op::Print => {
+                self.pop(A);
+                // values from 128 are UTF-8 encoded, as the interpreter writes
+                // them: 0xC2 and the value, or from 192, 0xC3 and the value
+                // less 64
+                self.copy(A, C, D);
+                self.add(D, 127);
+                self.compare(C, D, E, F);
+                self.clear(E);
+                self.branch(
+                    F,
+                    E,
+                    |g| {
+                        g.copy(A, C, D);
+                        g.add(D, 191);
+                        g.compare(C, D, E, B);
+                        g.clear(E);
+                        g.add(C, 0xC2);
+                        g.branch(
+                            B,
+                            E,
+                            |g| {
+                                g.add(C, 1);
+                                g.goto(A);
+                                g.emit(&"-".repeat(64));
+                            },
+                            |_| {},
+                        );
+                        g.goto(C);
+                        g.emit(".[-]");
+                    },
+                    |_| {},
+                );
+                self.goto(A);
+                self.emit(".[-]");
+            },
+            Op::Input => {
+                // leaving the cell clear means EOF reads as 0, whether the BF
+                // implementation stores 0 or leaves the cell unchanged
+                self.goto(A);
+                self.emit(",");
+                self.push(A);
+            },
+            Op::Extension(_) => unreachable!("extensions are rejected by super::instruction"),
+        }
+    }
+}
+
+/// Compile `program` into BF which prints what running it with
+/// [`Computer`](crate::types::Computer) prints, given a BF implementation whose
+/// cells are the same size as StackCell's, which is unbounded to the right, and
+/// which reads EOF as 0 or leaves the cell unchanged. It differs from the
+/// interpreter in that:
+///
+/// - dividing by zero halts the program without saying why, since BF has no
+///   stderr to report it on as the interpreter does (so the output is the
+///   same, but the exit status isn't);
+/// - other errors, such as an unmatched bracket, are printed to the output as
+///   `Error: …` lines, where the interpreter prints them on stderr unless it's
+///   given `--classic-errors`;
+/// - with 32-bit cells, `;` doesn't reduce values from 256 to their low byte
+///   first, as the interpreter does, so they're written wrongly.
+pub fn compile(program: &Program) -> String {
+    let blocks = program.blocks();
+    let flag = |offset: usize| {
+        blocks
+            .iter()
+            .position(|block| block[0] == offset)
+            .map(|i| REGISTERS + i)
+    };
+    let home = REGISTERS + blocks.len() + FRAME;
+    let mut g = Generator {
+        out: String::new(),
+        position: 0,
+        home,
+        pending: 0,
+    };
+    g.add(home + RAIL, 1);
+    g.add(home + MARKER_A, 1);
+    g.add(home + MARKER_B, 1);
+    if !blocks.is_empty() {
+        g.add(RUN, 1);
+        g.add(REGISTERS, 1);
+    }
+    g.open(RUN);
+    for (i, block) in blocks.iter().enumerate() {
+        let own = REGISTERS + i;
+        g.open(own);
+        g.emit("-");
+        for &offset in block {
+            let instruction = match super::instruction(program, offset) {
+                Ok(instruction) => instruction,
+                Err(e) => {
+                    g.fail(&e);
+                    break;
+                },
+            };
+            g.instruction(&instruction.op);
+            if offset != *block.last().unwrap() {
+                continue;
+            }
+            let next = flag(instruction.next);
+            let goto = |g: &mut Generator, target: Option<usize>| {
+                match target {
+                    Some(target) => g.add(target, 1),
+                    None => g.clear(RUN),
+                }
+            };
+            match &instruction.op {
+                Op::Halt => g.clear(RUN),
+                Op::JumpIf { when, target } => {
+                    g.pop(A);
+                    let target = target.as_ref().map(|&target| flag(target));
+                    let taken = |g: &mut Generator| {
+                        match &target {
+                            Ok(target) => goto(g, *target),
+                            Err(e) => g.fail(e),
+                        }
+                    };
+                    let not_taken = |g: &mut Generator| goto(g, next);
+                    match when {
+                        Condition::NonZero => g.branch(A, B, taken, not_taken),
+                        Condition::Zero => g.branch(A, B, not_taken, taken),
+                    }
+                },
+                _ => goto(&mut g, next),
+            }
+        }
+        while g.pending > 0 {
+            g.close(OK);
+            g.pending -= 1;
+        }
+        g.close(own);
+    }
+    g.close(RUN);
+    g.out
+}
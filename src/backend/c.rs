@@ -0,0 +1,217 @@
+use std::fmt::Write;
+
+use crate::program::{BinaryOp, Condition, Op, Program};
+
+#[cfg(feature = "u32")]
+const CELL_TYPE: &str = "uint32_t";
+#[cfg(not(feature = "u32"))]
+const CELL_TYPE: &str = "uint8_t";
+
+const PRELUDE: &str = r#"#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+typedef CELL_TYPE cell_t;
+
+typedef struct {
+    cell_t *data;
+    size_t len;
+    size_t cap;
+} sc_stack;
+
+static sc_stack stack_a, stack_b;
+static sc_stack *primary = &stack_a, *secondary = &stack_b;
+static cell_t cell;
+
+static void push(cell_t value) {
+    if (primary->len == primary->cap) {
+        primary->cap = primary->cap ? primary->cap * 2 : 64;
+        primary->data = realloc(primary->data, primary->cap * sizeof(cell_t));
+        if (!primary->data) {
+            fputs("out of memory\n", stderr);
+            exit(1);
+        }
+    }
+    primary->data[primary->len++] = value;
+}
+
+static cell_t pop(void) {
+    return primary->len ? primary->data[--primary->len] : 0;
+}
+
+static void swap_stacks(void) {
+    sc_stack *tmp = primary;
+    primary = secondary;
+    secondary = tmp;
+}
+
+/* the interpreter prints each value as the Unicode scalar U+0000 to U+00FF */
+static void print(cell_t value) {
+    unsigned char c = (unsigned char)value;
+    if (c < 0x80) {
+        putchar(c);
+    } else {
+        putchar(0xC0 | (c >> 6));
+        putchar(0x80 | (c & 0x3F));
+    }
+    fflush(stdout);
+}
+
+static cell_t input(void) {
+    int c = getchar();
+    return c == EOF ? 0 : (cell_t)c;
+}
+
+/* errors are reported as the interpreter reports them, on stderr after the
+   output so far, exiting with 65 if the tape couldn't be read where execution
+   reached and 70 for anything else */
+static void report(const char *message, int status) {
+    fflush(stdout);
+    fprintf(stderr, "Error: %s\n", message);
+    exit(status);
+}
+
+static void fail(const char *message) {
+    report(message, 65);
+}
+
+static cell_t checked_divisor(cell_t value, const char *message) {
+    if (!value) {
+        report(message, 70);
+    }
+    return value;
+}
+
+"#;
+
+/// Escape `message` for use inside a C string literal.
+fn c_string(message: &str) -> String {
+    let mut out = String::from("\"");
+    for byte in message.bytes() {
+        match byte {
+            b'"' | b'\\' => {
+                out.push('\\');
+                out.push(byte as char);
+            },
+            0x20..=0x7E => out.push(byte as char),
+            _ => write!(out, "\\{:03o}", byte).unwrap(),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn binary_op(op: BinaryOp) -> String {
+    match op {
+        BinaryOp::Less => "l < r".to_string(),
+        BinaryOp::Greater => "l > r".to_string(),
+        BinaryOp::Equal => "l == r".to_string(),
+        BinaryOp::Add => "l + r".to_string(),
+        BinaryOp::Subtract => "l - r".to_string(),
+        BinaryOp::Multiply => "l * r".to_string(),
+        BinaryOp::Divide => {
+            format!(
+                "l / checked_divisor(r, {})",
+                c_string("attempt to divide by zero")
+            )
+        },
+        BinaryOp::Modulo => {
+            format!(
+                "l % checked_divisor(r, {})",
+                c_string(
+                    "attempt to calculate the remainder with a divisor of zero"
+                )
+            )
+        },
+        BinaryOp::Xor => "l ^ r".to_string(),
+        BinaryOp::And => "l & r".to_string(),
+        BinaryOp::Or => "l | r".to_string(),
+    }
+}
+
+fn goto(program: &Program, offset: usize) -> String {
+    if offset >= program.tape.len() {
+        "goto halt;".to_string()
+    } else {
+        format!("goto L{};", offset)
+    }
+}
+
+/// Transpile `program` into a single self-contained C file, which behaves
+/// exactly like running it with [`Computer`](crate::types::Computer).
+pub fn compile(program: &Program) -> String {
+    let mut out = String::new();
+    writeln!(out, "/* generated by stackcell */").unwrap();
+    out.push_str(&PRELUDE.replace("CELL_TYPE", CELL_TYPE));
+    writeln!(out, "int main(void) {{").unwrap();
+    writeln!(out, "    cell_t l, r;").unwrap();
+    writeln!(out, "    (void)l;").unwrap();
+    writeln!(out, "    (void)r;").unwrap();
+    let reachable = program.reachable();
+    for (i, &offset) in reachable.iter().enumerate() {
+        writeln!(out, "L{}:", offset).unwrap();
+        let instruction = match super::instruction(program, offset) {
+            Ok(instruction) => instruction,
+            Err(e) => {
+                writeln!(out, "    fail({});", c_string(&e)).unwrap();
+                continue;
+            },
+        };
+        match &instruction.op {
+            Op::Push(values) => {
+                for value in values {
+                    writeln!(out, "    push({});", value).unwrap();
+                }
+            },
+            Op::Skip(_) => {},
+            Op::JumpIf { when, target } => {
+                let test = match when {
+                    Condition::Zero => "!pop()",
+                    Condition::NonZero => "pop()",
+                };
+                match target {
+                    Ok(target) => {
+                        writeln!(out, "    if ({}) {}", test, goto(program, *target))
+                            .unwrap();
+                    },
+                    Err(e) => {
+                        writeln!(out, "    if ({}) fail({});", test, c_string(e))
+                            .unwrap();
+                    },
+                }
+            },
+            Op::Halt => {
+                writeln!(out, "    goto halt;").unwrap();
+                continue;
+            },
+            Op::Duplicate => writeln!(out, "    l = pop(); push(l); push(l);").unwrap(),
+            Op::Drop => writeln!(out, "    pop();").unwrap(),
+            Op::Store => writeln!(out, "    cell = pop();").unwrap(),
+            Op::Load => writeln!(out, "    push(cell);").unwrap(),
+            Op::Swap => {
+                writeln!(out, "    l = pop(); r = pop(); push(l); push(r);").unwrap()
+            },
+            Op::SwapStacks => writeln!(out, "    swap_stacks();").unwrap(),
+            Op::Not => writeln!(out, "    push(!pop());").unwrap(),
+            Op::Invert => writeln!(out, "    push((cell_t)~pop());").unwrap(),
+            Op::Binary(op) => {
+                writeln!(
+                    out,
+                    "    l = pop(); r = pop(); push((cell_t)({}));",
+                    binary_op(*op)
+                )
+                .unwrap()
+            },
+            Op::Print => writeln!(out, "    print(pop());").unwrap(),
+            Op::Input => writeln!(out, "    push(input());").unwrap(),
+            Op::Extension(_) => unreachable!("extensions are rejected by super::instruction"),
+        }
+        if reachable.get(i + 1) != Some(&instruction.next) {
+            writeln!(out, "    {}", goto(program, instruction.next)).unwrap();
+        }
+    }
+    writeln!(out, "halt:").unwrap();
+    writeln!(out, "    return 0;").unwrap();
+    writeln!(out, "}}").unwrap();
+    out
+}
@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::program::{BinaryOp, Condition, Op, Program};
+
+#[cfg(feature = "u32")]
+mod cell {
+    pub const BYTES: u32 = 4;
+    pub const LOAD: &str = "i32.load";
+    pub const STORE: &str = "i32.store";
+    /// Applied after any operation which may overflow the cell.
+    pub const WRAP: &str = "";
+}
+#[cfg(not(feature = "u32"))]
+mod cell {
+    pub const BYTES: u32 = 1;
+    pub const LOAD: &str = "i32.load8_u";
+    pub const STORE: &str = "i32.store8";
+    /// Applied after any operation which may overflow the cell.
+    pub const WRAP: &str = "\n    i32.const 255\n    i32.and";
+}
+
+/// The runtime: both stacks are interleaved in linear memory (element `i` of
+/// stack `b` lives at cell `2i + b`), so they can each grow without bound.
+const PRELUDE: &str = r#"(module
+  (import "env" "read_byte" (func $read_byte (result i32)))
+  (import "env" "write_byte" (func $write_byte (param i32)))
+  (memory (export "memory") 1)
+  (global $primary_len (mut i32) (i32.const 0))
+  (global $secondary_len (mut i32) (i32.const 0))
+  (global $primary_base (mut i32) (i32.const 0))
+  (global $secondary_base (mut i32) (i32.const 1))
+  (global $cell (mut i32) (i32.const 0))
+  (func $address (param $index i32) (result i32)
+    (i32.mul
+      (i32.add (i32.shl (local.get $index) (i32.const 1)) (global.get $primary_base))
+      (i32.const CELL_BYTES)))
+  (func $push (param $value i32)
+    (local $address i32)
+    (local.set $address (call $address (global.get $primary_len)))
+    (if (i32.gt_u
+          (i32.add (local.get $address) (i32.const CELL_BYTES))
+          (i32.mul (memory.size) (i32.const 65536)))
+      (then
+        (if (i32.eq (memory.grow (i32.const 1)) (i32.const -1))
+          (then unreachable))))
+    (CELL_STORE (local.get $address) (local.get $value))
+    (global.set $primary_len (i32.add (global.get $primary_len) (i32.const 1))))
+  (func $pop (result i32)
+    (if (result i32) (global.get $primary_len)
+      (then
+        (global.set $primary_len (i32.sub (global.get $primary_len) (i32.const 1)))
+        (CELL_LOAD (call $address (global.get $primary_len))))
+      (else (i32.const 0))))
+  (func $swap_stacks
+    (local $tmp i32)
+    (local.set $tmp (global.get $primary_len))
+    (global.set $primary_len (global.get $secondary_len))
+    (global.set $secondary_len (local.get $tmp))
+    (local.set $tmp (global.get $primary_base))
+    (global.set $primary_base (global.get $secondary_base))
+    (global.set $secondary_base (local.get $tmp)))
+  (func $input (result i32)
+    (local $byte i32)
+    (local.set $byte (call $read_byte))
+    (select (i32.const 0) (local.get $byte) (i32.lt_s (local.get $byte) (i32.const 0))))
+"#;
+
+fn binary_op(op: BinaryOp) -> String {
+    let (instruction, wraps) = match op {
+        BinaryOp::Less => ("i32.lt_u", false),
+        BinaryOp::Greater => ("i32.gt_u", false),
+        BinaryOp::Equal => ("i32.eq", false),
+        BinaryOp::Add => ("i32.add", true),
+        BinaryOp::Subtract => ("i32.sub", true),
+        BinaryOp::Multiply => ("i32.mul", true),
+        BinaryOp::Divide => ("i32.div_u", false),
+        BinaryOp::Modulo => ("i32.rem_u", false),
+        BinaryOp::Xor => ("i32.xor", false),
+        BinaryOp::And => ("i32.and", false),
+        BinaryOp::Or => ("i32.or", false),
+    };
+    format!(
+        "    call $pop\n    local.set $l\n    call $pop\n    local.set $r\n    \
+         local.get $l\n    local.get $r\n    {}{}\n    call $push\n",
+        instruction,
+        if wraps { cell::WRAP } else { "" }
+    )
+}
+
+/// Write every byte of `message` with `write_byte`, as the interpreter would
+/// print it on failure.
+fn fail(out: &mut String, message: &str) {
+    for byte in format!("Error: {}\n", message).bytes() {
+        writeln!(out, "    i32.const {}\n    call $write_byte", byte).unwrap();
+    }
+    writeln!(out, "    return").unwrap();
+}
+
+/// Transpile `program` into the WebAssembly text format.
+///
+/// The module imports `env.read_byte`, which should return the next byte of
+/// input or a negative number at EOF, and `env.write_byte`, which is given the
+/// low byte of each printed value. It exports its `memory` and a `run`
+/// function which executes the program. Runtime errors are written through
+/// `write_byte` like the interpreter's, and dividing by zero traps.
+pub fn compile(program: &Program) -> String {
+    let mut out = PRELUDE
+        .replace("CELL_BYTES", &cell::BYTES.to_string())
+        .replace("CELL_LOAD", cell::LOAD)
+        .replace("CELL_STORE", cell::STORE);
+    let blocks = program.blocks();
+    let index: HashMap<usize, usize> = blocks
+        .iter()
+        .enumerate()
+        .map(|(i, block)| (block[0], i))
+        .collect();
+    // blocks are dispatched on their index; one past the last block halts
+    let target = |offset: usize| index.get(&offset).copied().unwrap_or(blocks.len());
+    writeln!(out, "  (func (export \"run\")").unwrap();
+    writeln!(out, "    (local $block i32) (local $l i32) (local $r i32)").unwrap();
+    writeln!(out, "    loop $dispatch").unwrap();
+    writeln!(out, "    block $halt").unwrap();
+    for i in (0..blocks.len()).rev() {
+        writeln!(out, "    block $b{}", i).unwrap();
+    }
+    writeln!(out, "    local.get $block").unwrap();
+    write!(out, "    br_table").unwrap();
+    for i in 0..blocks.len() {
+        write!(out, " $b{}", i).unwrap();
+    }
+    writeln!(out, " $halt").unwrap();
+    for block in &blocks {
+        writeln!(out, "    end").unwrap();
+        writeln!(out, "    ;; offset {}", block[0]).unwrap();
+        for &offset in block {
+            let instruction = match super::instruction(program, offset) {
+                Ok(instruction) => instruction,
+                Err(e) => {
+                    fail(&mut out, &e);
+                    break;
+                },
+            };
+            match &instruction.op {
+                Op::Push(values) => {
+                    for value in values {
+                        writeln!(out, "    i32.const {}\n    call $push", value).unwrap();
+                    }
+                },
+                Op::Skip(_) => {},
+                Op::JumpIf { when, target: jump } => {
+                    writeln!(out, "    call $pop").unwrap();
+                    if *when == Condition::Zero {
+                        writeln!(out, "    i32.eqz").unwrap();
+                    }
+                    match jump {
+                        Ok(jump) => {
+                            writeln!(
+                                out,
+                                "    if\n    i32.const {}\n    local.set $block\n    \
+                                 br $dispatch\n    end",
+                                target(*jump)
+                            )
+                            .unwrap();
+                        },
+                        Err(e) => {
+                            writeln!(out, "    if").unwrap();
+                            fail(&mut out, e);
+                            writeln!(out, "    end").unwrap();
+                        },
+                    }
+                },
+                Op::Halt => writeln!(out, "    return").unwrap(),
+                Op::Duplicate => {
+                    writeln!(
+                        out,
+                        "    call $pop\n    local.tee $l\n    call $push\n    \
+                         local.get $l\n    call $push"
+                    )
+                    .unwrap()
+                },
+                Op::Drop => writeln!(out, "    call $pop\n    drop").unwrap(),
+                Op::Store => writeln!(out, "    call $pop\n    global.set $cell").unwrap(),
+                Op::Load => writeln!(out, "    global.get $cell\n    call $push").unwrap(),
+                Op::Swap => {
+                    writeln!(
+                        out,
+                        "    call $pop\n    local.set $l\n    call $pop\n    \
+                         local.set $r\n    local.get $l\n    call $push\n    \
+                         local.get $r\n    call $push"
+                    )
+                    .unwrap()
+                },
+                Op::SwapStacks => writeln!(out, "    call $swap_stacks").unwrap(),
+                Op::Not => writeln!(out, "    call $pop\n    i32.eqz\n    call $push").unwrap(),
+                Op::Invert => {
+                    writeln!(
+                        out,
+                        "    call $pop\n    i32.const -1\n    i32.xor{}\n    call $push",
+                        cell::WRAP
+                    )
+                    .unwrap()
+                },
+                Op::Binary(op) => out.push_str(&binary_op(*op)),
+                Op::Print => {
+                    writeln!(
+                        out,
+                        "    call $pop\n    i32.const 255\n    i32.and\n    call $write_byte"
+                    )
+                    .unwrap()
+                },
+                Op::Input => writeln!(out, "    call $input\n    call $push").unwrap(),
+                Op::Extension(_) => {
+                    unreachable!("extensions are rejected by super::instruction")
+                },
+            }
+            if offset == *block.last().unwrap() {
+                writeln!(
+                    out,
+                    "    i32.const {}\n    local.set $block\n    br $dispatch",
+                    target(instruction.next)
+                )
+                .unwrap();
+            }
+        }
+    }
+    writeln!(out, "    end").unwrap();
+    writeln!(out, "    end").unwrap();
+    writeln!(out, "  )").unwrap();
+    writeln!(out, ")").unwrap();
+    out
+}
+
+/// Compile `program` into a binary WebAssembly module; see [`compile`].
+pub fn assemble(program: &Program) -> Result<Vec<u8>, String> {
+    wat::parse_str(compile(program)).map_err(|e| e.to_string())
+}
@@ -0,0 +1,193 @@
+//! A BF front-end: parsing, translation to StackCell, and a reference
+//! interpreter to check translations against.
+//!
+//! The BF tape is kept on the two stacks: the current cell is the top of the
+//! primary stack, with the cells to its left below it, and the cells to its
+//! right are on the secondary stack, nearest first. Since popping an empty
+//! stack gives zero, the tape is unbounded in both directions.
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::num::Wrapping;
+
+use crate::types::CellSize;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    /// A run of `+` and `-`, folded into the net change
+    Add(isize),
+    /// A run of `>` and `<`, folded into the net movement
+    Move(isize),
+    Output,
+    Input,
+    /// `[-]` or `[+]`
+    Clear,
+    Loop(Vec<Instruction>),
+}
+
+/// Parse BF source, folding runs of instructions and recognising clear loops.
+/// Characters other than the eight instructions are comments.
+pub fn parse(source: &str) -> Result<Vec<Instruction>, String> {
+    // the loops currently open, with the line and column of their `[`
+    let mut open: Vec<(Vec<Instruction>, usize, usize)> = vec![];
+    let mut current = vec![];
+    for (line_no, line) in source.lines().enumerate() {
+        for (column, c) in line.chars().enumerate() {
+            match c {
+                '+' | '-' => {
+                    let add = if c == '+' { 1 } else { -1 };
+                    match current.last_mut() {
+                        Some(Instruction::Add(n)) => *n += add,
+                        _ => current.push(Instruction::Add(add)),
+                    }
+                },
+                '>' | '<' => {
+                    let movement = if c == '>' { 1 } else { -1 };
+                    match current.last_mut() {
+                        Some(Instruction::Move(n)) => *n += movement,
+                        _ => current.push(Instruction::Move(movement)),
+                    }
+                },
+                '.' => current.push(Instruction::Output),
+                ',' => current.push(Instruction::Input),
+                '[' => {
+                    open.push((std::mem::take(&mut current), line_no + 1, column + 1));
+                },
+                ']' => {
+                    let (outer, _, _) = open.pop().ok_or_else(|| {
+                        format!(
+                            "Unmatched ']' at line {}, column {}",
+                            line_no + 1,
+                            column + 1
+                        )
+                    })?;
+                    let body = std::mem::replace(&mut current, outer);
+                    current.push(match body.as_slice() {
+                        [Instruction::Add(1)] | [Instruction::Add(-1)] => {
+                            Instruction::Clear
+                        },
+                        _ => Instruction::Loop(body),
+                    });
+                },
+                _ => {},
+            }
+            // drop runs which cancelled out, so they can't split a clear loop
+            if let Some(Instruction::Add(0) | Instruction::Move(0)) = current.last() {
+                current.pop();
+            }
+        }
+    }
+    match open.pop() {
+        Some((_, line, column)) => {
+            Err(format!("Unmatched '[' at line {}, column {}", line, column))
+        },
+        None => Ok(current),
+    }
+}
+
+/// Push `amount` as a sequence of `#NN` literals, each applied with `op`.
+fn push_amount(out: &mut String, mut amount: usize, op: &str) {
+    while amount > 0 {
+        let chunk = amount.min(0xFF);
+        write!(out, "#{:02X}{}", chunk, op).unwrap();
+        amount -= chunk;
+    }
+}
+
+fn translate(program: &[Instruction], out: &mut String) {
+    for instruction in program {
+        match instruction {
+            Instruction::Add(n) if *n > 0 => push_amount(out, *n as usize, "+"),
+            Instruction::Add(n) => push_amount(out, n.unsigned_abs(), "x-"),
+            Instruction::Move(n) if *n > 0 => {
+                for _ in 0..*n {
+                    out.push_str("X{X}");
+                }
+            },
+            Instruction::Move(n) => {
+                for _ in 0..n.unsigned_abs() {
+                    out.push_str("{X}X");
+                }
+            },
+            Instruction::Output => out.push_str(":;"),
+            Instruction::Input => out.push_str("`@"),
+            Instruction::Clear => out.push_str("`#00"),
+            Instruction::Loop(body) => {
+                out.push_str(":[");
+                translate(body, out);
+                out.push_str(":]");
+            },
+        }
+    }
+}
+
+/// Translate a parsed BF program into StackCell.
+pub fn to_stackcell(program: &[Instruction]) -> String {
+    let mut out = String::new();
+    translate(program, &mut out);
+    out
+}
+
+struct Tape {
+    cells: VecDeque<Wrapping<CellSize>>,
+    position: usize,
+}
+
+impl Tape {
+    fn move_by(&mut self, n: isize) {
+        if n < 0 {
+            let n = n.unsigned_abs();
+            while self.position < n {
+                self.cells.push_front(Wrapping(0));
+                self.position += 1;
+            }
+            self.position -= n;
+        } else {
+            self.position += n as usize;
+            if self.position >= self.cells.len() {
+                self.cells.resize(self.position + 1, Wrapping(0));
+            }
+        }
+    }
+
+    fn run(
+        &mut self,
+        program: &[Instruction],
+        input: &mut impl Iterator<Item = u8>,
+        output: &mut Vec<u8>,
+    ) {
+        for instruction in program {
+            match instruction {
+                Instruction::Add(n) => {
+                    self.cells[self.position] += Wrapping(*n as CellSize);
+                },
+                Instruction::Move(n) => self.move_by(*n),
+                // matching `;`, only the low byte of the cell is printed
+                #[allow(clippy::unnecessary_cast)]
+                Instruction::Output => output.push(self.cells[self.position].0 as u8),
+                Instruction::Input => {
+                    self.cells[self.position] =
+                        Wrapping(input.next().unwrap_or(0) as CellSize);
+                },
+                Instruction::Clear => self.cells[self.position] = Wrapping(0),
+                Instruction::Loop(body) => {
+                    while self.cells[self.position] != Wrapping(0) {
+                        self.run(body, input, output);
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Run a parsed BF program directly, with cells the same size as StackCell's
+/// and reading 0 at EOF, returning everything it printed.
+pub fn interpret(program: &[Instruction], input: &[u8]) -> Vec<u8> {
+    let mut tape = Tape {
+        cells: VecDeque::from(vec![Wrapping(0)]),
+        position: 0,
+    };
+    let mut output = vec![];
+    tape.run(program, &mut input.iter().copied(), &mut output);
+    output
+}
@@ -0,0 +1,79 @@
+//! The memory added by the heap extension: an array of cells, addressed by
+//! values popped from the stack.
+
+use std::num::Wrapping;
+
+use crate::types::CellSize;
+
+/// How many cells the heap can hold, unless [`Heap::limit`] says otherwise.
+pub const DEFAULT_HEAP_LIMIT: usize = 65_536;
+
+/// What happens to addresses past the end of the heap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Bounds {
+    /// Using one stops the program.
+    #[default]
+    Error,
+    /// They wrap around to the start of the heap.
+    Wrap,
+}
+
+/// An array of cells which grows as they're stored to. Cells which haven't
+/// been stored to read as 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Heap {
+    /// Every cell up to the highest one stored to.
+    pub cells: Vec<Wrapping<CellSize>>,
+    /// The number of addresses which can be used.
+    pub limit: usize,
+    pub bounds: Bounds,
+    /// The address most recently loaded from or stored to.
+    pub last: Option<usize>,
+}
+
+impl Default for Heap {
+    fn default() -> Heap {
+        Heap {
+            cells: vec![],
+            limit: DEFAULT_HEAP_LIMIT,
+            bounds: Bounds::default(),
+            last: None,
+        }
+    }
+}
+
+impl Heap {
+    fn address(&mut self, address: Wrapping<CellSize>) -> Result<usize, String> {
+        let address = address.0 as usize;
+        let address = match self.bounds {
+            _ if address < self.limit => address,
+            Bounds::Wrap if self.limit > 0 => address % self.limit,
+            _ => {
+                return Err(format!(
+                    "Heap address {} is out of bounds (the heap holds {} cells)",
+                    address, self.limit
+                ))
+            },
+        };
+        self.last = Some(address);
+        Ok(address)
+    }
+
+    pub fn load(&mut self, address: Wrapping<CellSize>) -> Result<Wrapping<CellSize>, String> {
+        let address = self.address(address)?;
+        Ok(self.cells.get(address).copied().unwrap_or(Wrapping(0)))
+    }
+
+    pub fn store(
+        &mut self,
+        address: Wrapping<CellSize>,
+        value: Wrapping<CellSize>,
+    ) -> Result<(), String> {
+        let address = self.address(address)?;
+        if address >= self.cells.len() {
+            self.cells.resize(address + 1, Wrapping(0));
+        }
+        self.cells[address] = value;
+        Ok(())
+    }
+}
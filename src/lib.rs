@@ -0,0 +1,9 @@
+pub mod asm;
+pub mod backend;
+pub mod bf;
+pub mod heap;
+pub mod sink;
+pub mod source_map;
+pub mod types;
+
+pub use stackcell_program::{dialect, program};
@@ -1,32 +1,621 @@
-use std::io::{stdin, stdout, Write};
+use std::io::{stdin, stdout, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
-use types::Computer;
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use stackcell::asm::diagnostic::Diagnostic;
+use stackcell::asm::source::Sources;
+use stackcell::dialect::{Dialect, Extension};
+use stackcell::heap::{Bounds, DEFAULT_HEAP_LIMIT};
+use stackcell::program::Program;
+use stackcell::sink::{Policy, Sink};
+use stackcell::source_map::SourceMap;
+use stackcell::types::{Computer, Encoding, Eof, Error, DEFAULT_MAX_CALL_DEPTH};
+use stackcell::{asm, backend, bf};
 
-mod types;
+mod repl;
+mod tui;
 
-fn main() -> Result<(), String> {
-    let mut args = std::env::args();
-    args.next();
-    match args.next() {
-        Some(program) => {
-            let tape = std::fs::read(program).expect("File not found");
-            if let Err(e) = Computer::new(&tape).run() {
-                println!("Error: {}", e)
-            }
-            Ok(())
-        },
-        None => {
-            loop {
-                print!("Enter program\n>>> ");
-                stdout().flush().unwrap();
-                let input = stdin();
-                let mut tape = String::new();
-                input.read_line(&mut tape).map_err(|e| e.to_string())?;
-                if let Err(e) = Computer::new(tape.trim().as_bytes()).run() {
-                    println!("Error: {}", e)
-                }
-                println!();
+// exit statuses for `run`, following sysexits.h
+const PARSE_ERROR: u8 = 65;
+const RUNTIME_ERROR: u8 = 70;
+const IO_ERROR: u8 = 74;
+/// The status the interpreter used to exit with when a program divided by
+/// zero, which was a panic.
+const CLASSIC_RUNTIME_ERROR: u8 = 101;
+
+/// An interpreter and toolchain for StackCell.
+///
+/// Given a file and no command, runs it as `stackcell run` would. Given nothing
+/// at all, starts an interactive prompt.
+#[derive(Parser)]
+#[command(version, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// A `.cel` program to run
+    file: Option<PathBuf>,
+    #[command(flatten)]
+    options: RunOptions,
+}
+
+#[derive(Args)]
+struct RunOptions {
+    /// Exit with the value on top of the primary stack (or 0 if it's empty)
+    /// when the program finishes, instead of 0
+    #[arg(long)]
+    exit_from_stack: bool,
+    /// Report errors the way older versions did: on stdout, exiting
+    /// successfully, except for division by zero which exits with status 101.
+    /// Programs such as samples/error_quine.cel rely on this.
+    #[arg(long)]
+    classic_errors: bool,
+    /// How often to write out the program's output (it's always written
+    /// before reading input and when the program finishes) [default: line if
+    /// stdout is a terminal, otherwise input]
+    #[arg(long, value_enum)]
+    flush: Option<Flush>,
+    /// How deeply subroutines (from the calls extension) can call each other
+    /// before the program is stopped
+    #[arg(long, default_value_t = DEFAULT_MAX_CALL_DEPTH)]
+    max_call_depth: usize,
+    /// How many cells the heap (from the heap extension) can hold
+    #[arg(long, default_value_t = DEFAULT_HEAP_LIMIT)]
+    heap_limit: usize,
+    /// What happens when the heap is used at an address past its limit
+    #[arg(long, value_enum, default_value_t = HeapBounds::Error)]
+    heap_bounds: HeapBounds,
+    /// A source map from `stackcell asm --source-map`, to say which line of
+    /// assembly an error happened on (and each instruction came from, with
+    /// --trace)
+    #[arg(long, value_name = "FILE")]
+    source_map: Option<PathBuf>,
+    /// Print each instruction to stderr before running it, with its offset, the
+    /// stacks and cell, and the heap cell last used (use --flush byte to keep
+    /// the program's output in step with it)
+    #[arg(long)]
+    trace: bool,
+    #[command(flatten)]
+    io: IoOptions,
+    #[command(flatten)]
+    dialect: DialectOptions,
+}
+
+#[derive(Args)]
+struct DialectOptions {
+    /// Enable an extension, as if the program's header asked for it
+    #[arg(short = 'x', long = "extension", value_enum)]
+    extensions: Vec<ExtensionArg>,
+}
+
+#[derive(Args)]
+struct IoOptions {
+    /// What `@` pushes once input runs out
+    #[arg(long, value_enum, default_value_t = EofArg::Zero)]
+    eof: EofArg,
+    /// How `@` and `;` read and write values
+    #[arg(long, value_enum, default_value_t = EncodingArg::Bytes)]
+    encoding: EncodingArg,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a `.cel` program
+    ///
+    /// If the program fails, the error is printed to stderr and the exit
+    /// status is 65 if the tape couldn't be read (such as an unknown
+    /// instruction or unmatched bracket), 70 for other errors in the program
+    /// (such as division by zero), or 74 if the program or its input or output
+    /// couldn't be read or written.
+    Run {
+        /// The program to run
+        file: PathBuf,
+        #[command(flatten)]
+        options: RunOptions,
+    },
+    /// Assemble `.casm` files into a `.cel` program, and print it
+    Asm {
+        /// The assembly to assemble, as one program with each file after the
+        /// one before (and macros defined in any of them usable in all)
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// A directory to look for included files in, after the directory of
+        /// the file including them (can be given more than once)
+        #[arg(short = 'I', long = "include", value_name = "DIR")]
+        include: Vec<PathBuf>,
+        /// Where to write the program, instead of stdout
+        #[arg(short)]
+        output: Option<PathBuf>,
+        /// Write a source map to FILE, saying which line of assembly each byte
+        /// of the program came from
+        #[arg(long, value_name = "FILE")]
+        source_map: Option<PathBuf>,
+        /// Print each line of assembly with the offset and code it assembled
+        /// to, instead of the program (which is still written to the file
+        /// given with -o)
+        #[arg(long)]
+        listing: bool,
+    },
+    /// Disassemble a `.cel` program into assembly
+    Disasm {
+        /// The program to disassemble
+        file: PathBuf,
+        #[command(flatten)]
+        dialect: DialectOptions,
+        /// End each line with a comment giving its offset into the program
+        #[arg(long)]
+        offsets: bool,
+    },
+    /// Report every error `.cel` programs could hit, such as invalid
+    /// instructions and unmatched brackets
+    Check {
+        /// The programs to check
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        #[command(flatten)]
+        dialect: DialectOptions,
+    },
+    /// Format a `.casm` file, printing the result
+    Fmt {
+        /// The assembly to format
+        file: PathBuf,
+        /// Overwrite the file instead of printing it
+        #[arg(short, long)]
+        write: bool,
+        /// Fail if the file isn't already formatted, instead of printing it
+        #[arg(long, conflicts_with = "write")]
+        check: bool,
+    },
+    /// Step through a `.cel` program in the interactive debugger
+    Debug {
+        /// The program to load (another can be loaded with F2)
+        file: Option<PathBuf>,
+        /// A source map from `stackcell asm --source-map` for the program, to
+        /// show which line of assembly is being run
+        #[arg(long, value_name = "FILE", requires = "file")]
+        source_map: Option<PathBuf>,
+        #[command(flatten)]
+        io: IoOptions,
+        #[command(flatten)]
+        dialect: DialectOptions,
+    },
+    /// Compile a `.cel` program into another language
+    Compile {
+        /// The language to compile into
+        #[arg(long = "to", value_enum)]
+        target: Target,
+        /// The program to compile
+        file: PathBuf,
+        /// Where to write the result, instead of stdout
+        #[arg(short)]
+        output: Option<PathBuf>,
+    },
+    /// Translate a BF program into StackCell
+    FromBf {
+        /// The BF program to translate
+        file: PathBuf,
+        /// Where to write the result, instead of stdout
+        #[arg(short)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Target {
+    /// BF, for an implementation with the same cell size as this one
+    Bf,
+    /// A self-contained C file
+    C,
+    /// WebAssembly, as text
+    Wat,
+    /// WebAssembly, as a binary module
+    Wasm,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Flush {
+    /// After every character
+    Byte,
+    /// After every line
+    Line,
+    /// Only when input is read, or as the buffer fills
+    Input,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum EofArg {
+    /// 0, the same as a NUL byte
+    Zero,
+    /// A value with every bit set, such as 255 (or -1)
+    AllOnes,
+    /// Nothing, leaving the stack unchanged
+    Unchanged,
+    /// The value read and then 1 on top of it, or 0 and then 0 at EOF
+    Flag,
+}
+
+impl From<EofArg> for Eof {
+    fn from(eof: EofArg) -> Eof {
+        match eof {
+            EofArg::Zero => Eof::Zero,
+            EofArg::AllOnes => Eof::AllOnes,
+            EofArg::Unchanged => Eof::Unchanged,
+            EofArg::Flag => Eof::Flag,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum EncodingArg {
+    /// A byte at a time, writing the low byte of each value
+    Bytes,
+    /// A UTF-8 encoded character at a time (only with 32-bit cells)
+    Utf8,
+}
+
+impl From<EncodingArg> for Encoding {
+    fn from(encoding: EncodingArg) -> Encoding {
+        match encoding {
+            EncodingArg::Bytes => Encoding::Bytes,
+            EncodingArg::Utf8 => Encoding::Utf8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum HeapBounds {
+    /// Stop the program
+    Error,
+    /// Wrap the address around to the start of the heap
+    Wrap,
+}
+
+impl From<HeapBounds> for Bounds {
+    fn from(bounds: HeapBounds) -> Bounds {
+        match bounds {
+            HeapBounds::Error => Bounds::Error,
+            HeapBounds::Wrap => Bounds::Wrap,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ExtensionArg {
+    /// `n` prints the top of the stack as a decimal number, and `N` reads one
+    Decimal,
+    /// `D`, `C` and `R` define, call and return from subroutines
+    Calls,
+    /// `g` and `p` load from and store to a heap of cells, by address
+    Heap,
+}
+
+impl From<ExtensionArg> for Extension {
+    fn from(extension: ExtensionArg) -> Extension {
+        match extension {
+            ExtensionArg::Decimal => Extension::Decimal,
+            ExtensionArg::Calls => Extension::Calls,
+            ExtensionArg::Heap => Extension::Heap,
+        }
+    }
+}
+
+impl IoOptions {
+    /// Exit with a usage error if `--encoding utf8` is given without 32-bit
+    /// cells, which would truncate each character to its low byte.
+    fn validate(&self) {
+        if cfg!(not(feature = "u32")) && self.encoding == EncodingArg::Utf8 {
+            Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "`--encoding utf8` needs 32-bit cells; build with `--features u32`",
+                )
+                .exit();
+        }
+    }
+}
+
+impl DialectOptions {
+    fn dialect(&self) -> Dialect {
+        Dialect::new(self.extensions.iter().map(|&extension| extension.into()))
+    }
+
+    /// Split the header off `file`, returning the dialect it asks for (with
+    /// these extensions enabled too) and the program after it.
+    fn parse<'a>(&self, file: &'a [u8]) -> Result<(Dialect, &'a [u8]), String> {
+        let (mut dialect, tape) = Dialect::parse(file)?;
+        for &extension in &self.extensions {
+            dialect.enable(extension.into());
+        }
+        Ok((dialect, tape))
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, String> {
+    std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn read_to_string(path: &Path) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn read_source_map(path: &Path) -> Result<SourceMap, String> {
+    SourceMap::parse(&read_to_string(path)?).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Write `code` to `output` if given, otherwise to stdout.
+fn write(output: Option<&Path>, code: &[u8]) -> Result<(), String> {
+    match output {
+        Some(path) => {
+            std::fs::write(path, code).map_err(|e| format!("{}: {}", path.display(), e))
+        },
+        None => stdout().write_all(code).map_err(|e| e.to_string()),
+    }
+}
+
+fn run(file: &Path, options: &RunOptions) -> ExitCode {
+    options.io.validate();
+    let tape = match read(file) {
+        Ok(tape) => tape,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return ExitCode::from(IO_ERROR);
+        },
+    };
+    let map = match options.source_map.as_deref().map(read_source_map).transpose() {
+        Ok(map) => map,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return ExitCode::from(IO_ERROR);
+        },
+    };
+    let policy = match options.flush {
+        Some(Flush::Byte) => Policy::Byte,
+        Some(Flush::Line) => Policy::Line,
+        Some(Flush::Input) => Policy::Input,
+        None if stdout().is_terminal() => Policy::Line,
+        None => Policy::Input,
+    };
+    let (dialect, tape) = match options.dialect.parse(&tape) {
+        Ok(parsed) => parsed,
+        Err(e) => return fail(Error::Parse(e), None, options),
+    };
+    let mut computer = Computer::with_io(tape, stdin(), Sink::new(stdout(), policy));
+    computer.eof = options.io.eof.into();
+    computer.encoding = options.io.encoding.into();
+    computer.dialect = dialect;
+    computer.max_call_depth = options.max_call_depth;
+    computer.heap.limit = options.heap_limit;
+    computer.heap.bounds = options.heap_bounds.into();
+    let result = if options.trace {
+        computer.run_traced(|computer| trace(computer, map.as_ref()))
+    } else {
+        computer.run()
+    };
+    match result {
+        Ok(()) if options.exit_from_stack => {
+            // truncating to the low byte is all a process can exit with
+            #[allow(clippy::unnecessary_cast)]
+            ExitCode::from(computer.get().0 as u8)
+        },
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            let location = map.as_ref().and_then(|map| map.locate(computer.instruction));
+            fail(e, location.map(|location| location.to_string()), options)
+        },
+    }
+}
+
+/// Describe the instruction `computer` is about to run on stderr, with the line
+/// of assembly it came from if `map` says, and the state it's run in.
+fn trace<R, W>(computer: &Computer<R, W>, map: Option<&SourceMap>) {
+    let position = computer.position;
+    let location = match map.and_then(|map| map.locate(position)) {
+        Some(location) => format!(" (at {})", location),
+        None => String::new(),
+    };
+    // the heap cell last used, once the heap has been
+    let heap = match computer.heap.last {
+        Some(address) => {
+            let value = computer.heap.cells.get(address).copied().unwrap_or_default();
+            format!(" | heap[{}]: {}", address, value)
+        },
+        None => String::new(),
+    };
+    eprintln!(
+        "{} {:?}{} | primary: {} | secondary: {} | cell: {}{}",
+        position,
+        computer.tape[position] as char,
+        location,
+        repl::show_stack(&computer.primary, Some(8)),
+        repl::show_stack(&computer.secondary, Some(8)),
+        computer.cell,
+        heap
+    );
+}
+
+/// Warn about any comments in the assembly `source` which start with `#`.
+fn warn_legacy_comments(file: &Path, source: &str) {
+    let lines = asm::legacy_comments(source);
+    let others = match lines.len() {
+        0 => return,
+        1 => String::new(),
+        2 => " and 1 other".to_string(),
+        n => format!(" and {} others", n - 1),
+    };
+    eprintln!(
+        "Warning: {}: comments starting with `#` are deprecated (line {}{}); start them with \
+         `;` instead, or convert them with `stackcell fmt --write`",
+        file.display(),
+        lines[0],
+        others
+    );
+}
+
+/// Report an error which stopped a program, and the line of assembly it
+/// happened on if known, returning the status to exit with.
+fn fail(e: Error, location: Option<String>, options: &RunOptions) -> ExitCode {
+    match e {
+        Error::Runtime(_) if options.classic_errors => {
+            eprintln!("{}", e);
+            ExitCode::from(CLASSIC_RUNTIME_ERROR)
+        },
+        _ if options.classic_errors => {
+            println!("Error: {}", e);
+            ExitCode::SUCCESS
+        },
+        _ => {
+            match location {
+                Some(location) => eprintln!("Error: {} (at {})", e, location),
+                None => eprintln!("Error: {}", e),
             }
+            ExitCode::from(match e {
+                Error::Parse(_) => PARSE_ERROR,
+                Error::Runtime(_) => RUNTIME_ERROR,
+                Error::Io(_) => IO_ERROR,
+            })
+        },
+    }
+}
+
+fn check(files: &[PathBuf], options: &DialectOptions) -> Result<(), String> {
+    let mut count = 0;
+    for file in files {
+        let errors = match options.parse(&read(file)?) {
+            Ok((dialect, tape)) => Program::with_dialect(tape, dialect).errors(),
+            Err(e) => vec![e],
+        };
+        for error in errors {
+            eprintln!("{}: {}", file.display(), error);
+            count += 1;
+        }
+    }
+    found(count)
+}
+
+/// Fail if any errors were found, saying how many.
+fn found(count: usize) -> Result<(), String> {
+    match count {
+        0 => Ok(()),
+        1 => Err("Found 1 error".to_string()),
+        _ => Err(format!("Found {} errors", count)),
+    }
+}
+
+/// Assemble `files` as one program, writing it to `output` (or stdout), along
+/// with a source map to `map` and a listing to stdout if asked for.
+fn assemble(
+    files: &[PathBuf],
+    include: Vec<PathBuf>,
+    output: Option<&Path>,
+    map: Option<&Path>,
+    listing: bool,
+) -> Result<(), String> {
+    let mut sources = Sources::new(include);
+    let mut indices = vec![];
+    for file in files {
+        let source = read_to_string(file)?;
+        warn_legacy_comments(file, &source);
+        indices.push(sources.add(file.display().to_string(), source));
+    }
+    let (program, spans) =
+        asm::assemble_files(&mut sources, &indices).map_err(|errors| report(&sources, errors))?;
+    if let Some(path) = map {
+        write(Some(path), asm::source_map(&sources, &spans).to_string().as_bytes())?;
+    }
+    if listing {
+        if let Some(path) = output {
+            write(Some(path), program.as_bytes())?;
+        }
+        write(None, asm::listing(&sources, &program, &spans).as_bytes())
+    } else {
+        // without a newline after it, which would be an unknown instruction
+        write(output, program.as_bytes())
+    }
+}
+
+/// Show each error in assembly read from `sources`, with the line it's on.
+fn report(sources: &Sources, errors: Vec<Diagnostic>) -> String {
+    for error in &errors {
+        eprint!("{}", error.render(sources));
+    }
+    found(errors.len()).unwrap_err()
+}
+
+fn fmt(file: &Path, write: bool, check: bool) -> Result<(), String> {
+    let source = read_to_string(file)?;
+    let formatted = asm::fmt::format(&source).map_err(|errors| {
+        let mut sources = Sources::default();
+        sources.add(file.display().to_string(), source.as_str());
+        report(&sources, errors)
+    })?;
+    if check {
+        if formatted != source {
+            return Err(format!("{} is not formatted", file.display()));
+        }
+        Ok(())
+    } else if write {
+        self::write(Some(file), formatted.as_bytes())
+    } else {
+        self::write(None, formatted.as_bytes())
+    }
+}
+
+fn compile(target: Target, file: &Path, output: Option<&Path>) -> Result<(), String> {
+    let program = Program::parse(&read(file)?)?;
+    if let Some(extension) = program.dialect.extensions().first() {
+        return Err(format!("The {} extension can't be compiled", extension));
+    }
+    let code = match target {
+        Target::Bf => backend::bf::compile(&program).into_bytes(),
+        Target::C => backend::c::compile(&program).into_bytes(),
+        Target::Wat => backend::wasm::compile(&program).into_bytes(),
+        Target::Wasm => backend::wasm::assemble(&program)?,
+    };
+    write(output, &code)
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match (cli.command, cli.file) {
+        (Some(Command::Run { file, options }), _) => return run(&file, &options),
+        (None, Some(file)) => return run(&file, &cli.options),
+        (Some(Command::Asm { files, include, output, source_map, listing }), _) => {
+            assemble(&files, include, output.as_deref(), source_map.as_deref(), listing)
+        },
+        (Some(Command::Disasm { file, offsets, dialect }), _) => {
+            read(&file)
+                .and_then(|file| {
+                    let (dialect, tape) = dialect.parse(&file)?;
+                    asm::disasm::disassemble(&Program::with_dialect(tape, dialect), offsets)
+                })
+                .and_then(|source| write(None, source.as_bytes()))
+        },
+        (Some(Command::Check { files, dialect }), _) => check(&files, &dialect),
+        (Some(Command::Fmt { file, write, check }), _) => fmt(&file, write, check),
+        (Some(Command::Debug { file, source_map, io, dialect }), _) => {
+            io.validate();
+            source_map.as_deref().map(read_source_map).transpose().and_then(|map| {
+                let (eof, encoding) = (io.eof.into(), io.encoding.into());
+                tui::debug(file.as_deref(), map, eof, encoding, dialect.dialect())
+                    .map_err(|e| e.to_string())
+            })
+        },
+        (Some(Command::Compile { target, file, output }), _) => {
+            compile(target, &file, output.as_deref())
+        },
+        (Some(Command::FromBf { file, output }), _) => {
+            read_to_string(&file)
+                .and_then(|source| bf::parse(&source))
+                .and_then(|program| write(output.as_deref(), bf::to_stackcell(&program).as_bytes()))
+        },
+        (None, None) => repl::repl(),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
         },
     }
 }
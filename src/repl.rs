@@ -0,0 +1,314 @@
+//! The interactive prompt, which runs each line entered against the same
+//! stacks and cell.
+
+use std::collections::VecDeque;
+use std::io::{stdout, Error, Read, Stdout, Write};
+use std::num::Wrapping;
+
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use stackcell::dialect::Dialect;
+use stackcell::types::{CellSize, Computer};
+
+const HELP: &str = "\
+Each line entered is run as a program, keeping the stacks and cell from the
+last one. Lines starting with `:` and one of these words are commands (other
+lines starting with `:`, which duplicates, are run as programs):
+  :stack          show both stacks in full
+  :cell           show the cell
+  :heap           show the heap (from the heap extension) in full
+  :reset          clear the stacks, the cell, the heap and any queued input
+  :load <file>    run a program from a file
+  :input <text>   queue input for `@`, which reads 0 once it runs out; text
+                  may be quoted, with \\n, \\t, \\\\, \\\" and \\xNN escapes
+  :undo           go back to before the last line or command
+  :help           show this message
+  :quit           exit (as does Ctrl-D)";
+
+/// The words which make a line starting with `:` a command.
+const COMMANDS: &[&str] =
+    &["stack", "cell", "heap", "reset", "load", "input", "undo", "help", "quit", "q"];
+
+/// Input queued with `:input`.
+#[derive(Clone, Default)]
+struct Queue(VecDeque<u8>);
+
+impl Read for Queue {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        match (buf.first_mut(), self.0.pop_front()) {
+            (Some(slot), Some(byte)) => {
+                *slot = byte;
+                Ok(1)
+            },
+            _ => Ok(0),
+        }
+    }
+}
+
+/// Stdout, remembering whether the last thing written ended a line so that
+/// the prompt always starts on a fresh one.
+struct Terminal {
+    stdout: Stdout,
+    at_line_start: bool,
+}
+
+impl Write for Terminal {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let written = self.stdout.write(buf)?;
+        if let Some(&last) = buf[..written].last() {
+            self.at_line_start = last == b'\n';
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.stdout.flush()
+    }
+}
+
+/// Everything `:undo` restores.
+#[derive(Clone)]
+struct Snapshot {
+    primary: Vec<Wrapping<CellSize>>,
+    secondary: Vec<Wrapping<CellSize>>,
+    cell: Wrapping<CellSize>,
+    heap: Vec<Wrapping<CellSize>>,
+    input: Queue,
+    /// The extensions enabled, which `:load` can add to.
+    dialect: Dialect,
+}
+
+struct Repl {
+    computer: Computer<Queue, Terminal>,
+    history: Vec<Snapshot>,
+}
+
+impl Repl {
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            primary: self.computer.primary.clone(),
+            secondary: self.computer.secondary.clone(),
+            cell: self.computer.cell,
+            heap: self.computer.heap.cells.clone(),
+            input: self.computer.input.clone(),
+            dialect: self.computer.dialect.clone(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.computer.primary = snapshot.primary;
+        self.computer.secondary = snapshot.secondary;
+        self.computer.cell = snapshot.cell;
+        self.computer.heap.cells = snapshot.heap;
+        self.computer.input = snapshot.input;
+        self.computer.dialect = snapshot.dialect;
+    }
+
+    /// Show the stacks and cell briefly, and how much of the heap is used.
+    fn show(&self) {
+        print!(
+            "primary: {} | secondary: {} | cell: {}",
+            show_stack(&self.computer.primary, Some(8)),
+            show_stack(&self.computer.secondary, Some(8)),
+            self.computer.cell
+        );
+        match self.computer.heap.cells.len() {
+            0 => println!(),
+            1 => println!(" | heap: 1 cell"),
+            len => println!(" | heap: {} cells", len),
+        }
+    }
+
+    fn run(&mut self, tape: &[u8]) {
+        self.computer.load(tape);
+        if let Err(e) = self.computer.run() {
+            println!("Error: {}", e);
+            self.computer.output.at_line_start = true;
+        }
+        if !self.computer.output.at_line_start {
+            println!();
+            self.computer.output.at_line_start = true;
+        }
+        self.show();
+    }
+
+    /// Handle a `:` command, returning false to exit.
+    fn command(&mut self, command: &str, argument: &str) -> bool {
+        let before = self.snapshot();
+        match command {
+            "stack" => {
+                println!("primary:   {}", show_stack(&self.computer.primary, None));
+                println!("secondary: {}", show_stack(&self.computer.secondary, None));
+                return true;
+            },
+            "cell" => {
+                println!("cell: {}", self.computer.cell);
+                return true;
+            },
+            "heap" => {
+                println!("heap: {}", show_stack(&self.computer.heap.cells, None));
+                return true;
+            },
+            "reset" => {
+                self.restore(Snapshot {
+                    primary: vec![],
+                    secondary: vec![],
+                    cell: Wrapping(0),
+                    heap: vec![],
+                    input: Queue::default(),
+                    dialect: self.computer.dialect.clone(),
+                });
+                self.show();
+            },
+            "load" if argument.is_empty() => {
+                println!("Usage: :load <file>");
+                return true;
+            },
+            "load" => {
+                let file = match std::fs::read(argument) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        println!("Error: {}: {}", argument, e);
+                        return true;
+                    },
+                };
+                // the extensions a file asks for stay enabled for later lines
+                match Dialect::parse(&file) {
+                    Ok((dialect, tape)) => {
+                        for &extension in dialect.extensions() {
+                            self.computer.dialect.enable(extension);
+                        }
+                        self.run(tape);
+                    },
+                    Err(e) => {
+                        println!("Error: {}: {}", argument, e);
+                        return true;
+                    },
+                }
+            },
+            "input" => {
+                match unescape(argument) {
+                    Ok(bytes) => self.computer.input.0.extend(bytes),
+                    Err(e) => {
+                        println!("Error: {}", e);
+                        return true;
+                    },
+                }
+            },
+            "undo" => {
+                match self.history.pop() {
+                    Some(snapshot) => {
+                        self.restore(snapshot);
+                        self.show();
+                    },
+                    None => println!("Nothing to undo"),
+                }
+                return true;
+            },
+            "help" => {
+                println!("{}", HELP);
+                return true;
+            },
+            "quit" | "q" => return false,
+            _ => unreachable!("{:?} isn't in COMMANDS", command),
+        }
+        self.history.push(before);
+        true
+    }
+}
+
+/// The values on `stack`, bottom first, showing at most `limit` of the top
+/// values.
+pub fn show_stack(stack: &[Wrapping<CellSize>], limit: Option<usize>) -> String {
+    if stack.is_empty() {
+        return "(empty)".to_string();
+    }
+    let skip = limit.map_or(0, |limit| stack.len().saturating_sub(limit));
+    let values = stack[skip..]
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+    if skip > 0 {
+        format!("({} more) {}", skip, values)
+    } else {
+        values
+    }
+}
+
+/// Parse the argument to `:input`: either the text as written, or a quoted
+/// string with escapes.
+fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let inner = match text.strip_prefix('"') {
+        Some(rest) => rest.strip_suffix('"').ok_or("Unterminated string")?,
+        None => return Ok(text.as_bytes().to_vec()),
+    };
+    let mut bytes = vec![];
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('0') => bytes.push(0),
+            Some('\\') => bytes.push(b'\\'),
+            Some('"') => bytes.push(b'"'),
+            Some('x') => {
+                let digits: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&digits, 16)
+                    .map_err(|_| format!("Invalid escape \\x{}", digits))?;
+                bytes.push(byte);
+            },
+            Some(c) => return Err(format!("Invalid escape \\{}", c)),
+            None => return Err("Unterminated escape".to_string()),
+        }
+    }
+    Ok(bytes)
+}
+
+/// Run the prompt until the user exits.
+pub fn repl() -> Result<(), String> {
+    let mut editor = DefaultEditor::new().map_err(|e| e.to_string())?;
+    let mut repl = Repl {
+        computer: Computer::with_io(
+            &[],
+            Queue::default(),
+            Terminal {
+                stdout: stdout(),
+                at_line_start: true,
+            },
+        ),
+        history: vec![],
+    };
+    println!("Enter a program to run it, or :help for help");
+    loop {
+        let line = match editor.readline(">>> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => return Ok(()),
+            Err(e) => return Err(e.to_string()),
+        };
+        let _ = editor.add_history_entry(&line);
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let command = line
+            .strip_prefix(':')
+            .map(|command| command.split_once(' ').unwrap_or((command, "")))
+            .filter(|(command, _)| COMMANDS.contains(command));
+        if let Some((command, argument)) = command {
+            if !repl.command(command, argument.trim()) {
+                return Ok(());
+            }
+        } else {
+            let before = repl.snapshot();
+            repl.run(line.as_bytes());
+            repl.history.push(before);
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! Buffered output for `;`.
+//!
+//! A [`Computer`](crate::types::Computer) writes each `;` separately, which is
+//! slow if every one reaches the terminal or pipe on its own. Giving it a
+//! [`Sink`] instead collects that output and only passes it on as often as the
+//! [`Policy`] asks. Whatever the policy, the computer flushes its output before
+//! reading input and when it stops running, so prompts are always shown.
+
+use std::io::{BufWriter, Error, Write};
+
+/// When a [`Sink`] passes its output on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// After every byte, for output which should appear as it's written.
+    Byte,
+    /// After every newline.
+    Line,
+    /// Only when the buffer fills, input is read, or the program stops.
+    Input,
+}
+
+/// A buffered writer which flushes according to a [`Policy`].
+pub struct Sink<W: Write> {
+    inner: BufWriter<W>,
+    policy: Policy,
+}
+
+impl<W: Write> Sink<W> {
+    pub fn new(inner: W, policy: Policy) -> Sink<W> {
+        Sink {
+            inner: BufWriter::new(inner),
+            policy,
+        }
+    }
+
+    pub fn policy(&self) -> Policy {
+        self.policy
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.get_ref()
+    }
+
+    /// Flush anything buffered, and return the writer being written to.
+    pub fn into_inner(self) -> Result<W, Error> {
+        self.inner.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+impl<W: Write> Write for Sink<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let written = self.inner.write(buf)?;
+        match self.policy {
+            Policy::Byte => self.inner.flush()?,
+            Policy::Line if buf[..written].contains(&b'\n') => self.inner.flush()?,
+            Policy::Line | Policy::Input => {},
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()
+    }
+}
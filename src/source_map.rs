@@ -0,0 +1,153 @@
+//! Source maps, which say which line of assembly each byte of an assembled
+//! program came from.
+//!
+//! A source map is a text file, written by `stackcell asm --source-map`:
+//!
+//! ```text
+//! stackcell-map 1
+//! file prog.casm
+//! file std/io.casm
+//! 0 0 1 1
+//! 3 1 12 5
+//! ```
+//!
+//! After the first line, each `file` line names a file of assembly (numbered
+//! from 0 in the order they're given), and every other line says that the
+//! bytes from a tape offset up to the next such line came from a file, line
+//! and column, both counting from 1. Offsets count from the start of the
+//! program after its header, as the interpreter does, and are in order.
+
+use std::fmt;
+
+/// The first line of a source map.
+pub const HEADER: &str = "stackcell-map 1";
+
+/// Where some code came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location<'a> {
+    pub file: &'a str,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+/// The offset some code starts at, and the index of the file, the line and
+/// the column it came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Mapping {
+    offset: usize,
+    file: usize,
+    line: usize,
+    column: usize,
+}
+
+/// Which line of assembly each byte of a program came from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    files: Vec<String>,
+    /// Sorted by offset, with at most one for each.
+    mappings: Vec<Mapping>,
+}
+
+impl SourceMap {
+    /// Say that the code from `offset` onwards came from `line` and `column`
+    /// of `file`. Offsets must be added in order; code at the same offset as
+    /// the last added is taken to have come from the new place instead.
+    pub fn add(&mut self, offset: usize, file: &str, line: usize, column: usize) {
+        let file = match self.files.iter().position(|path| path == file) {
+            Some(index) => index,
+            None => {
+                self.files.push(file.to_string());
+                self.files.len() - 1
+            },
+        };
+        let mapping = Mapping {
+            offset,
+            file,
+            line,
+            column,
+        };
+        match self.mappings.last_mut() {
+            Some(last) if last.offset == offset => *last = mapping,
+            Some(last) if (last.file, last.line, last.column) == (file, line, column) => {},
+            _ => self.mappings.push(mapping),
+        }
+    }
+
+    /// Where the code at `offset` came from, if anywhere.
+    pub fn locate(&self, offset: usize) -> Option<Location<'_>> {
+        let index = self.mappings.partition_point(|mapping| mapping.offset <= offset);
+        let mapping = self.mappings.get(index.checked_sub(1)?)?;
+        Some(Location {
+            file: &self.files[mapping.file],
+            line: mapping.line,
+            column: mapping.column,
+        })
+    }
+
+    /// Read a source map written by [`Display`](fmt::Display).
+    pub fn parse(text: &str) -> Result<SourceMap, String> {
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(format!("A source map must start with {:?}", HEADER));
+        }
+        let mut map = SourceMap::default();
+        for (number, line) in lines.enumerate() {
+            // counting from 1, after the header
+            let number = number + 2;
+            if let Some(path) = line.strip_prefix("file ") {
+                map.files.push(path.to_string());
+                continue;
+            }
+            let invalid =
+                || format!("Line {} of the source map isn't a mapping: {:?}", number, line);
+            let fields: Vec<usize> = line
+                .split(' ')
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .map_err(|_| invalid())?;
+            let mapping = match fields[..] {
+                [offset, file, line, column] if file < map.files.len() => Mapping {
+                    offset,
+                    file,
+                    line,
+                    column,
+                },
+                [_, file, _, _] => {
+                    return Err(format!(
+                        "Line {} of the source map uses file {}, which isn't given",
+                        number, file
+                    ))
+                },
+                _ => return Err(invalid()),
+            };
+            if map.mappings.last().is_some_and(|last| last.offset >= mapping.offset) {
+                return Err(format!("Line {} of the source map is out of order", number));
+            }
+            map.mappings.push(mapping);
+        }
+        Ok(map)
+    }
+}
+
+impl fmt::Display for SourceMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        for file in &self.files {
+            writeln!(f, "file {}", file)?;
+        }
+        for mapping in &self.mappings {
+            writeln!(
+                f,
+                "{} {} {} {}",
+                mapping.offset, mapping.file, mapping.line, mapping.column
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -1,17 +1,140 @@
+//! The interactive debugger, which steps through a program showing its stacks,
+//! cell and heap.
+
 use std::cmp::{max, min};
-use std::io::{stdout, Error, ErrorKind, Write};
+use std::collections::VecDeque;
+use std::io::{stdout, Error, Read, Write};
 use std::num::Wrapping;
+use std::path::Path;
 
-use crossterm::event::read;
+use crossterm::cursor::MoveTo;
+use crossterm::event::Event::Key;
+use crossterm::event::{read, KeyCode, KeyEvent, KeyModifiers};
 use crossterm::terminal::{self, disable_raw_mode, enable_raw_mode, ClearType};
 use crossterm::{cursor, event, execute, style};
+use stackcell::dialect::Dialect;
+use stackcell::heap::Heap;
+use stackcell::source_map::SourceMap;
+use stackcell::types::{CellSize, Computer, Encoding, Eof};
+
+mod layout;
+use layout::{CLEAR_LINE, ENTRY_BOX, INPUT_BOX, TEMPLATE, TEMPLATE_BOTTOM};
+
+/// Input for `@`, prompting for a key whenever a character is needed.
+#[derive(Default)]
+struct Keyboard {
+    pending: VecDeque<u8>,
+    has_found_eof: bool,
+}
+
+impl Keyboard {
+    fn prompt(&mut self) -> Result<(), Error> {
+        loop {
+            execute!(stdout(), MoveTo(0, 17))?;
+            print!("{INPUT_BOX}");
+            match read()? {
+                Key(KeyEvent {
+                    code: KeyCode::Char('D' | 'd'),
+                    modifiers: KeyModifiers::CONTROL,
+                }) => {
+                    execute!(stdout(), MoveTo(0, 17))?;
+                    print!("{TEMPLATE_BOTTOM}");
+                    self.has_found_eof = true;
+                    return Ok(());
+                },
+                Key(KeyEvent {
+                    code: KeyCode::Char(c),
+                    ..
+                }) => {
+                    execute!(stdout(), MoveTo(0, 17))?;
+                    print!("{TEMPLATE_BOTTOM}");
+                    self.pending.extend(c.to_string().bytes());
+                    return Ok(());
+                },
+                Key(KeyEvent {
+                    code: KeyCode::Enter,
+                    ..
+                }) => {
+                    execute!(stdout(), MoveTo(0, 17))?;
+                    print!("{TEMPLATE_BOTTOM}");
+                    self.pending.push_back(b'\n');
+                    return Ok(());
+                },
+                Key(KeyEvent {
+                    code: KeyCode::Esc,
+                    ..
+                }) => {
+                    execute!(stdout(), MoveTo(0, 17))?;
+                    print!("{TEMPLATE_BOTTOM}");
+                    return Err(Error::other("User exit"));
+                },
+                _ => (),
+            }
+        }
+    }
+}
 
-mod constants;
-mod types;
-use constants::{CLEAR_LINE, ENTRY_BOX, TEMPLATE, TEMPLATE_BOTTOM};
-use types::CellSize;
+impl Read for Keyboard {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.pending.is_empty() && !self.has_found_eof {
+            self.prompt()?;
+        }
+        match self.pending.pop_front() {
+            Some(byte) => {
+                buf[0] = byte;
+                Ok(1)
+            },
+            // after Ctrl-D, this and all further reads are EOF, handled as `--eof` says
+            None => Ok(0),
+        }
+    }
+}
+
+/// A computer being debugged, which keeps its output to be shown in the
+/// output panel.
+type Debuggee = Computer<Keyboard, Vec<u8>>;
+
+/// Load `file`, in the dialect its header asks for plus the extensions in
+/// `dialect`.
+fn load(file: &[u8], eof: Eof, encoding: Encoding, dialect: &Dialect) -> Debuggee {
+    let (header, program) = match Dialect::parse(file) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            let mut computer = Computer::with_io(&[], Keyboard::default(), vec![]);
+            computer.output = format!("Error: {e}").into_bytes();
+            computer.running = false;
+            return computer;
+        },
+    };
+    let mut computer = Computer::with_io(program, Keyboard::default(), vec![]);
+    computer.eof = eof;
+    computer.encoding = encoding;
+    computer.dialect = header;
+    for &extension in dialect.extensions() {
+        computer.dialect.enable(extension);
+    }
+    computer
+}
+
+fn step(computer: &mut Debuggee) {
+    if let Err(e) = computer.step() {
+        computer
+            .output
+            .extend_from_slice(format!("Error: {e}").as_bytes());
+        computer.running = false;
+    }
+}
 
-fn run_app() -> Result<(), Error> {
+fn run_app(
+    file: Option<&Path>,
+    mut map: Option<SourceMap>,
+    eof: Eof,
+    encoding: Encoding,
+    dialect: Dialect,
+) -> Result<(), Error> {
     let mut stdout = stdout();
     execute!(
         stdout,
@@ -19,23 +142,17 @@ fn run_app() -> Result<(), Error> {
         cursor::MoveTo(0, 0)
     )?;
     write!(stdout, "{TEMPLATE}")?;
-    let mut program = String::new();
-    let mut filename = "No file".to_string();
-    let mut computer = types::Computer::new(&program);
-    let mut output = String::new();
+    let (mut program, mut filename) = match file {
+        Some(file) => (std::fs::read(file)?, file.display().to_string()),
+        None => (vec![], "No file".to_string()),
+    };
+    let mut computer = load(&program, eof, encoding, &dialect);
     let mut running_as_fast_as_possible = false;
     loop {
-        print_info(&mut stdout, &computer, &filename, &output)?;
+        print_info(&mut stdout, &computer, &filename, map.as_ref())?;
         #[allow(clippy::redundant_else)]
         if running_as_fast_as_possible && computer.running {
-            match computer.step(&mut output) {
-                Ok(()) => (),
-                Err(e) => {
-                    output.push_str(&format!("Error: {e}"));
-                    computer.running = false;
-                    running_as_fast_as_possible = false;
-                },
-            }
+            step(&mut computer);
             continue;
         } else {
             running_as_fast_as_possible = false;
@@ -51,47 +168,43 @@ fn run_app() -> Result<(), Error> {
                 ..
             }) => {
                 if let Err(e) = get_string(&mut stdout, &mut filename) {
-                    output.push_str(&e.to_string());
+                    computer.output.extend_from_slice(e.to_string().as_bytes());
                     continue;
                 }
-                program = match std::fs::read_to_string(&filename) {
+                program = match std::fs::read(&filename) {
                     Ok(s) => s,
                     Err(e) => {
-                        output.push_str(&e.to_string());
+                        computer.output.extend_from_slice(e.to_string().as_bytes());
                         continue;
                     },
                 };
-                computer = types::Computer::new(&program);
+                // the source map was for the program given on the command line
+                map = None;
+                computer = load(&program, eof, encoding, &dialect);
             },
             event::Event::Key(event::KeyEvent {
                 code: event::KeyCode::F(3),
                 ..
             }) => {
                 filename = "<stdin>".to_string();
-                if let Err(e) = get_string(&mut stdout, &mut program) {
-                    output.push_str(&e.to_string());
+                let mut entered = String::new();
+                if let Err(e) = get_string(&mut stdout, &mut entered) {
+                    computer.output.extend_from_slice(e.to_string().as_bytes());
                     continue;
                 }
-                computer = types::Computer::new(&program);
+                program = entered.into_bytes();
+                map = None;
+                computer = load(&program, eof, encoding, &dialect);
             },
             event::Event::Key(event::KeyEvent {
                 code: event::KeyCode::F(5),
                 ..
-            }) => {
-                match computer.step(&mut output) {
-                    Ok(()) => (),
-                    Err(e) => {
-                        output.push_str(&format!("Error: {e}"));
-                        computer.running = false;
-                    },
-                }
-            },
+            }) => step(&mut computer),
             event::Event::Key(event::KeyEvent {
                 code: event::KeyCode::F(9),
                 ..
             }) => {
-                output.clear();
-                computer = types::Computer::new(&program);
+                computer = load(&program, eof, encoding, &dialect);
             },
             event::Event::Key(event::KeyEvent {
                 code: event::KeyCode::F(10),
@@ -129,7 +242,7 @@ fn get_string(stdout: &mut std::io::Stdout, out: &mut String) -> Result<(), Erro
                 execute!(stdout, cursor::MoveTo(0, 17))?;
                 write!(stdout, "{TEMPLATE_BOTTOM}")?;
                 execute!(stdout, terminal::Clear(ClearType::FromCursorDown))?;
-                return Err(Error::new(ErrorKind::Other, "Cancelled"));
+                return Err(Error::other("Cancelled"));
             },
             event::Event::Key(event::KeyEvent {
                 code: event::KeyCode::Enter,
@@ -159,9 +272,9 @@ fn get_string(stdout: &mut std::io::Stdout, out: &mut String) -> Result<(), Erro
 
 fn print_info(
     stdout: &mut std::io::Stdout,
-    computer: &types::Computer,
+    computer: &Debuggee,
     filename: &str,
-    output: &str,
+    map: Option<&SourceMap>,
 ) -> Result<(), Error> {
     let prog_len = min(computer.tape.len(), 75);
     let prog_spaces = 75 - prog_len;
@@ -193,15 +306,26 @@ fn print_info(
     for _ in 0..=prog_spaces {
         write!(stdout, " ")?;
     }
+    // the line of assembly the next instruction came from, in the space left
+    let location = map
+        .and_then(|map| map.locate(computer.position))
+        .map_or(String::new(), |location| location.to_string());
+    let length = location.chars().count();
+    if length > 23 {
+        let end: String = location.chars().skip(length - 21).collect();
+        write!(stdout, "< {end}")?;
+    } else {
+        write!(stdout, "{location:>23}")?;
+    }
     execute!(stdout, cursor::MoveTo(10, 3))?;
     #[cfg(feature = "u32")]
     {
         write!(
             stdout,
             "{:2x} {:2x} {:2x} {:2x}",
-            computer.cell.0 >> 24 as u8,
-            computer.cell.0 >> 16 as u8,
-            computer.cell.0 >> 8 as u8,
+            (computer.cell.0 >> 24) as u8,
+            (computer.cell.0 >> 16) as u8,
+            (computer.cell.0 >> 8) as u8,
             computer.cell.0 as u8,
         )?;
     }
@@ -237,7 +361,8 @@ fn print_info(
     }
     print_stack(&computer.primary, 5, stdout)?;
     print_stack(&computer.secondary, 7, stdout)?;
-    print_output(output, stdout)?;
+    print_heap(&computer.heap, 9, stdout)?;
+    print_output(&String::from_utf8_lossy(&computer.output), stdout)?;
     execute!(stdout, cursor::MoveTo(0, 17))?;
     Ok(())
 }
@@ -271,8 +396,37 @@ fn print_stack(
     Ok(())
 }
 
+/// Print the 13 heap cells around the one last used (highlighted), labelled
+/// with the address of the first unless that's 0. Cells past the highest one
+/// stored to are left blank.
+fn print_heap(heap: &Heap, line_no: u16, stdout: &mut std::io::Stdout) -> Result<(), Error> {
+    let start = heap.last.map_or(0, |last| last / 13 * 13);
+    execute!(stdout, cursor::MoveTo(2, line_no))?;
+    if start == 0 {
+        write!(stdout, "{:<8}", "Heap:")?;
+    } else {
+        write!(stdout, "{:<8}", format!("@{}:", start))?;
+    }
+    for i in 0..13 {
+        let val = match heap.cells.get(start + i) {
+            Some(val) => represent_value(val.0),
+            None => "    ".to_string(),
+        };
+        #[allow(clippy::cast_possible_truncation)]
+        execute!(stdout, cursor::MoveTo((13 + 7 * i) as u16, line_no))?;
+        if heap.last == Some(start + i) {
+            execute!(stdout, style::SetAttribute(style::Attribute::Reverse))?;
+        }
+        write!(stdout, "{val}")?;
+        if heap.last == Some(start + i) {
+            execute!(stdout, style::SetAttribute(style::Attribute::NoReverse))?;
+        }
+    }
+    Ok(())
+}
+
 fn print_output(output: &str, stdout: &mut std::io::Stdout) -> Result<(), Error> {
-    let mut out_buffer = [[' '; 99]; 5];
+    let mut out_buffer = [[' '; 99]; 4];
     let mut y = 0;
     let mut x = 0;
     for char in output.chars() {
@@ -293,15 +447,15 @@ fn print_output(output: &str, stdout: &mut std::io::Stdout) -> Result<(), Error>
             y += 1;
             x = 0;
         }
-        if y == 5 {
-            y = 4;
+        if y == 4 {
+            y = 3;
             out_buffer[0].fill(' ');
             out_buffer.rotate_left(1);
         }
     }
     for (y, row) in out_buffer.iter().enumerate() {
         #[allow(clippy::cast_possible_truncation)]
-        execute!(stdout, cursor::MoveTo(2, (9 + y) as u16))?;
+        execute!(stdout, cursor::MoveTo(2, (11 + y) as u16))?;
         for char in row.iter() {
             write!(stdout, "{char}")?;
         }
@@ -333,10 +487,21 @@ const fn printable(c: u8) -> char {
     }
 }
 
-fn main() -> Result<(), Error> {
+/// Run the debugger until the user exits, starting with `file` loaded if given
+/// (with `map` saying which line of assembly each byte of it came from), with
+/// `@` pushing according to `eof` once input runs out, reading and writing
+/// text as `encoding` says, and with the extensions in `dialect` as well as
+/// those each program's header asks for.
+pub fn debug(
+    file: Option<&Path>,
+    map: Option<SourceMap>,
+    eof: Eof,
+    encoding: Encoding,
+    dialect: Dialect,
+) -> Result<(), Error> {
     enable_raw_mode()?;
     execute!(stdout(), cursor::Hide)?;
-    let result = run_app();
+    let result = run_app(file, map, eof, encoding, dialect);
     execute!(stdout(), cursor::Show)?;
     disable_raw_mode()?;
     result
@@ -13,13 +13,13 @@ pub const TEMPLATE: &str = concat_with_crlf!(
     "║ Stack A: ║      │      │      │      │      │      │      │      │      │      │      │      │      ║",
     "╠══════════╬══════╪══════╪══════╪══════╪══════╪══════╪══════╪══════╪══════╪══════╪══════╪══════╪══════╣",
     "║ Stack B: ║      │      │      │      │      │      │      │      │      │      │      │      │      ║",
+    "╠══════════╬══════╪══════╪══════╪══════╪══════╪══════╪══════╪══════╪══════╪══════╪══════╪══════╪══════╣",
+    "║ Heap:    ║      │      │      │      │      │      │      │      │      │      │      │      │      ║",
     "╠══════════╩══════╧══════╧══════╧══════╧══════╧══════╧══════╧══════╧══════╧══════╧══════╧══════╧══════╣",
     "║                                                                                                     ║",
     "║                                                                                                     ║",
     "║                                                                                                     ║",
     "║                                                                                                     ║",
-    "║                                                                                                     ║",
-    "║                                                                                                     ║",
     "╠════════════╦════════════════╦════════════════════╦═══════════╦════════════╦═════════════════════════╣",
     "║ ESC - exit ║ F2 - load file ║ F3 - enter program ║ F5 - step ║ F9 - reset ║ F10 - run at full speed ║",
     "╚════════════╩════════════════╩════════════════════╩═══════════╩════════════╩═════════════════════════╝",
@@ -46,6 +46,6 @@ pub const ENTRY_BOX: &str = concat_with_crlf!(
 #[rustfmt::skip]
 pub const INPUT_BOX: &str = concat_with_crlf!(
     "╠════════════╩═════════╦══════╩════════════════════╩═══════════╩════════════╩═════════════════════════╣",
-    "║ Waiting for input... ║ Press Ctrl-D for End of Input (this and all further inputs are null)         ║",
+    "║ Waiting for input... ║ Ctrl-D ends input (reads then follow --eof)                                  ║",
     "╚══════════════════════╩══════════════════════════════════════════════════════════════════════════════╝",
 );
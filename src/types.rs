@@ -1,43 +1,150 @@
-use std::io::{self, stdout, Read, Write};
+use std::fmt;
+use std::io::{self, Read, Write};
 use std::num::Wrapping;
 
+use crate::dialect::{Dialect, Extension};
+use crate::heap::Heap;
+pub use crate::program::unhex;
+use crate::program::Definitions;
+use crate::sink::{Policy, Sink};
+
 #[cfg(feature = "u32")]
-type CellSize = u32;
+pub type CellSize = u32;
 #[cfg(not(feature = "u32"))]
-type CellSize = u8;
+pub type CellSize = u8;
+
+/// Why a program stopped before reaching its end.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The tape couldn't be read where execution reached: an unknown
+    /// instruction, an invalid hex digit, or a missing bracket or literal.
+    Parse(String),
+    /// The program did something invalid, such as dividing by zero.
+    Runtime(String),
+    /// Reading input or writing output failed.
+    Io(String),
+}
 
-pub fn unhex(c: u8) -> Result<u8, String> {
-    return match c {
-        b'0'..=b'9' => Ok(c - b'0'),
-        b'a'..=b'f' => Ok(c - b'a' + 10),
-        b'A'..=b'F' => Ok(c - b'A' + 10),
-        _ => Err(format!("Invalid hex character: {}", c)),
-    };
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Parse(message) | Error::Runtime(message) | Error::Io(message) => {
+                write!(f, "{}", message)
+            },
+        }
+    }
 }
 
-pub struct Computer {
+impl std::error::Error for Error {}
+
+/// What `@` pushes once input runs out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Eof {
+    /// Push 0, as a NUL byte would.
+    #[default]
+    Zero,
+    /// Push a cell with every bit set (255, or -1 as a signed value).
+    AllOnes,
+    /// Push nothing, leaving the stack unchanged.
+    Unchanged,
+    /// Push the value read and then 1, or 0 and then 0 at EOF, so the top of
+    /// the stack says whether anything was read.
+    Flag,
+}
+
+/// How `@` and `;` turn values into text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    /// `@` reads a single byte, and `;` writes the low byte of a value as a
+    /// character (UTF-8 encoded, so 128 and above take two bytes).
+    #[default]
+    Bytes,
+    /// `@` reads a UTF-8 encoded character, and `;` writes a value as one.
+    /// Invalid input, and values which aren't characters, become U+FFFD. A
+    /// byte which can't continue the character before it is read again, as the
+    /// start of the next one.
+    Utf8,
+}
+
+/// How deeply subroutines can call each other before the program is stopped,
+/// unless [`Computer::max_call_depth`] says otherwise.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 10_000;
+
+pub struct Computer<R = io::Stdin, W = Sink<io::Stdout>> {
     pub tape: Vec<u8>,
     pub position: usize,
+    /// The offset of the instruction being run (or last run), which is where
+    /// an error stopping the program happened.
+    pub instruction: usize,
     pub primary: Vec<Wrapping<CellSize>>,
     pub secondary: Vec<Wrapping<CellSize>>,
-    pub io_reader: io::Stdin,
+    pub input: R,
+    pub output: W,
     pub running: bool,
     pub cell: Wrapping<CellSize>,
+    pub eof: Eof,
+    pub encoding: Encoding,
+    /// The extensions which give meanings to otherwise unknown instructions.
+    pub dialect: Dialect,
+    /// Where each subroutine being run returns to, innermost last.
+    pub calls: Vec<usize>,
+    pub max_call_depth: usize,
+    /// The cells used by the heap extension.
+    pub heap: Heap,
+    /// A byte read by `@` which wasn't part of the character before it, to be
+    /// read again by the next `@`.
+    pending: Option<u8>,
+    /// Where the tape's subroutines start, found when the first one is called.
+    definitions: Option<Definitions>,
 }
 
 impl Computer {
+    /// Create a computer using stdin and stdout, flushing output after each
+    /// line.
     pub fn new(tape: &[u8]) -> Computer {
+        Computer::with_io(tape, io::stdin(), Sink::new(io::stdout(), Policy::Line))
+    }
+}
+
+impl<R: Read, W: Write> Computer<R, W> {
+    /// Create a computer which reads `@` from `input` and writes `;` to
+    /// `output`, instead of stdin and stdout. `output` is flushed before
+    /// reading input and when [`run`](Computer::run) returns, but not after
+    /// each `;`; wrap it in a [`Sink`] to choose how often it's passed on.
+    pub fn with_io(tape: &[u8], input: R, output: W) -> Computer<R, W> {
         Computer {
             tape: tape.to_vec(),
             position: 0,
+            instruction: 0,
             primary: vec![],
             secondary: vec![],
-            io_reader: io::stdin(),
+            input,
+            output,
             running: true,
             cell: Wrapping(0),
+            eof: Eof::default(),
+            encoding: Encoding::default(),
+            dialect: Dialect::default(),
+            calls: vec![],
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            heap: Heap::default(),
+            pending: None,
+            definitions: None,
         }
     }
 
+    /// Replace the tape with `tape` and start running it from the beginning,
+    /// keeping the stacks, the cell and the heap (but not any subroutine calls,
+    /// which would return into the old tape).
+    pub fn load(&mut self, tape: &[u8]) {
+        self.tape = tape.to_vec();
+        self.position = 0;
+        self.instruction = 0;
+        self.running = true;
+        self.calls.clear();
+        self.definitions = None;
+    }
+
     pub fn get(&mut self) -> Wrapping<CellSize> {
         match self.primary.pop() {
             Some(value) => value,
@@ -45,18 +152,113 @@ impl Computer {
         }
     }
 
-    pub fn read_tape(&mut self) -> Result<u8, String> {
+    fn read_byte(&mut self) -> Result<Option<u8>, Error> {
+        if let Some(byte) = self.pending.take() {
+            return Ok(Some(byte));
+        }
+        let mut buf = [0u8];
+        match self.input.read(&mut buf) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(buf[0])),
+            Err(e) => Err(Error::Io(e.to_string())),
+        }
+    }
+
+    /// Read a value for `@`, or `None` at EOF.
+    fn read_value(&mut self) -> Result<Option<Wrapping<CellSize>>, Error> {
+        let first = match self.read_byte()? {
+            Some(byte) => byte,
+            None => return Ok(None),
+        };
+        if self.encoding == Encoding::Bytes {
+            return Ok(Some(Wrapping(first as CellSize)));
+        }
+        // truncating to the cell size, as for a literal
+        let invalid = Wrapping(char::REPLACEMENT_CHARACTER as CellSize);
+        // the bytes which can follow the lead byte, as the second byte of a
+        // character which isn't overlong, a surrogate or past U+10FFFF
+        let (length, second) = match first {
+            0x00..=0x7F => return Ok(Some(Wrapping(first as CellSize))),
+            0xC2..=0xDF => (2, 0x80..=0xBF),
+            0xE0 => (3, 0xA0..=0xBF),
+            0xE1..=0xEC | 0xEE..=0xEF => (3, 0x80..=0xBF),
+            0xED => (3, 0x80..=0x9F),
+            0xF0 => (4, 0x90..=0xBF),
+            0xF1..=0xF3 => (4, 0x80..=0xBF),
+            0xF4 => (4, 0x80..=0x8F),
+            _ => return Ok(Some(invalid)),
+        };
+        let mut c = u32::from(first) & (0x7F >> length);
+        for i in 1..length {
+            let expected = if i == 1 { second.clone() } else { 0x80..=0xBF };
+            match self.read_byte()? {
+                Some(byte) if expected.contains(&byte) => c = c << 6 | u32::from(byte & 0x3F),
+                Some(byte) => {
+                    // the byte might start the next character, so it's read again
+                    self.pending = Some(byte);
+                    return Ok(Some(invalid));
+                },
+                None => return Ok(Some(invalid)),
+            }
+        }
+        #[allow(clippy::unnecessary_cast)]
+        Ok(Some(Wrapping(c as CellSize)))
+    }
+
+    /// Read a decimal integer for `N`: whitespace, then an optional `-` and
+    /// digits, consuming the byte after them. Returns 0 if there are no digits,
+    /// or `None` if input ran out first.
+    fn read_number(&mut self) -> Result<Option<Wrapping<CellSize>>, Error> {
+        let mut byte = self.read_byte()?;
+        while matches!(byte, Some(b) if b.is_ascii_whitespace()) {
+            byte = self.read_byte()?;
+        }
+        let negative = byte == Some(b'-');
+        if negative {
+            byte = self.read_byte()?;
+        }
+        let mut value = Wrapping(0);
+        let mut digits = false;
+        while let Some(digit @ b'0'..=b'9') = byte {
+            value = value * Wrapping(10) + Wrapping((digit - b'0') as CellSize);
+            digits = true;
+            byte = self.read_byte()?;
+        }
+        if !digits && byte.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(if negative { -value } else { value }))
+    }
+
+    /// Push a value read by `@` or `N`, or `None` at EOF.
+    fn push_input(&mut self, value: Option<Wrapping<CellSize>>) {
+        match (value, self.eof) {
+            (None, Eof::Zero) => self.primary.push(Wrapping(0)),
+            (None, Eof::AllOnes) => self.primary.push(!Wrapping(0)),
+            (None, Eof::Unchanged) => {},
+            (None, Eof::Flag) => self.primary.extend([Wrapping(0), Wrapping(0)]),
+            (Some(value), Eof::Flag) => self.primary.extend([value, Wrapping(1)]),
+            (Some(value), _) => self.primary.push(value),
+        }
+    }
+
+    pub fn read_tape(&mut self) -> Result<u8, Error> {
         if self.position >= self.tape.len() {
-            Err("Indexed out of bounds!".to_string())
+            Err(Error::Parse("Indexed out of bounds!".to_string()))
         } else {
             Ok(self.tape[self.position])
         }
     }
 
-    pub fn step(&mut self) -> Result<(), String> {
+    pub fn step(&mut self) -> Result<(), Error> {
         if !self.running {
-            return Err("Computer is not running!".to_string());
+            return Err(Error::Runtime("Computer is not running!".to_string()));
+        } else if self.position >= self.tape.len() {
+            // this is fine, just halt
+            self.running = false;
+            return Ok(());
         }
+        self.instruction = self.position;
         let instruction = self.read_tape()?;
         self.position += 1;
         match instruction {
@@ -74,14 +276,14 @@ impl Computer {
                 self.position += 1;
             },
             b'#' => {
-                let d0 = unhex(self.read_tape()?)?;
+                let d0 = unhex(self.read_tape()?).map_err(Error::Parse)?;
                 self.position += 1;
-                let d1 = unhex(self.read_tape()?)?;
+                let d1 = unhex(self.read_tape()?).map_err(Error::Parse)?;
                 self.position += 1;
                 self.primary.push(Wrapping((d0 * 16 + d1) as CellSize));
             },
             b'1'..=b'9' => {
-                self.position += unhex(instruction)? as usize;
+                self.position += unhex(instruction).map_err(Error::Parse)? as usize;
             },
             b'[' => {
                 if self.get() == Wrapping(0) {
@@ -100,14 +302,15 @@ impl Computer {
             b']' => {
                 if self.get() != Wrapping(0) {
                     let mut depth = 0;
-                    self.position -= 2;
+                    // running off the start of the tape is reported by read_tape
+                    self.position = self.position.wrapping_sub(2);
                     while depth != 0 || self.read_tape()? != b'[' {
                         if self.read_tape()? == b']' {
                             depth += 1;
                         } else if self.read_tape()? == b'[' {
                             depth -= 1;
                         }
-                        self.position -= 1;
+                        self.position = self.position.wrapping_sub(1);
                     }
                     self.position += 1;
                 }
@@ -129,14 +332,15 @@ impl Computer {
             b')' => {
                 if self.get() == Wrapping(0) {
                     let mut depth = 0;
-                    self.position -= 2;
+                    // running off the start of the tape is reported by read_tape
+                    self.position = self.position.wrapping_sub(2);
                     while depth != 0 || self.read_tape()? != b'(' {
                         if self.read_tape()? == b')' {
                             depth += 1;
                         } else if self.read_tape()? == b'(' {
                             depth -= 1;
                         }
-                        self.position -= 1;
+                        self.position = self.position.wrapping_sub(1);
                     }
                     self.position += 1;
                 }
@@ -208,11 +412,19 @@ impl Computer {
             b'/' => {
                 let left = self.get();
                 let right = self.get();
+                if right == Wrapping(0) {
+                    return Err(Error::Runtime("attempt to divide by zero".to_string()));
+                }
                 self.primary.push(left / right);
             },
             b'%' => {
                 let left = self.get();
                 let right = self.get();
+                if right == Wrapping(0) {
+                    return Err(Error::Runtime(
+                        "attempt to calculate the remainder with a divisor of zero".to_string(),
+                    ));
+                }
                 self.primary.push(left % right);
             },
             b'^' => {
@@ -241,28 +453,113 @@ impl Computer {
                 }
             },
             b';' => {
-                print!("{}", self.get().0 as u8 as char);
-                stdout().flush().unwrap();
+                let val = self.get().0;
+                // depending on CellSize, one of these casts is unnecessary and the
+                // other is the intended conversion (or truncation, for bytes)
+                #[allow(clippy::unnecessary_cast)]
+                let c = match self.encoding {
+                    Encoding::Bytes => val as u8 as char,
+                    Encoding::Utf8 => {
+                        char::from_u32(val as u32).unwrap_or(char::REPLACEMENT_CHARACTER)
+                    },
+                };
+                write!(self.output, "{}", c).map_err(|e| Error::Io(e.to_string()))?;
             },
             b'@' => {
-                let mut buf = [0u8];
-                let result =
-                    self.io_reader.read(&mut buf).map_err(|e| e.to_string())?;
-                if result == 0 {
-                    self.primary.push(Wrapping(0));
-                } else {
-                    self.primary.push(Wrapping(buf[0] as CellSize));
+                // anything waiting to be written might be a prompt
+                self.output.flush().map_err(|e| Error::Io(e.to_string()))?;
+                let value = self.read_value()?;
+                self.push_input(value);
+            },
+            _ => {
+                match self.dialect.extension(instruction) {
+                    Some(Extension::Decimal) => self.decimal(instruction)?,
+                    Some(Extension::Calls) => self.call(instruction)?,
+                    Some(Extension::Heap) => self.heap_access(instruction)?,
+                    None => {
+                        return Err(Error::Parse(format!(
+                            "Unknown instruction: {:?}",
+                            instruction as char
+                        )))
+                    },
                 }
             },
-            _ => return Err(format!("Unknown instruction: {:?}", instruction as char)),
         }
         Ok(())
     }
 
-    pub fn run(&mut self) -> Result<(), String> {
-        while self.position < self.tape.len() && self.running {
-            self.step()?;
+    /// Execute an instruction from the calls extension.
+    fn call(&mut self, instruction: u8) -> Result<(), Error> {
+        match instruction {
+            b'D' => {
+                self.read_tape()?;
+                self.position += 1;
+            },
+            b'C' => {
+                let label = self.read_tape()?;
+                self.position += 1;
+                let tape = &self.tape;
+                let definitions = self.definitions.get_or_insert_with(|| Definitions::new(tape));
+                let target = definitions.find(label).map_err(Error::Parse)?;
+                if self.calls.len() >= self.max_call_depth {
+                    return Err(Error::Runtime(format!(
+                        "Subroutines called more than {} deep",
+                        self.max_call_depth
+                    )));
+                }
+                self.calls.push(self.position);
+                self.position = target;
+            },
+            _ => {
+                self.position = self
+                    .calls
+                    .pop()
+                    .ok_or_else(|| Error::Runtime("Returned without a call".to_string()))?;
+            },
         }
         Ok(())
     }
+
+    /// Execute an instruction from the heap extension.
+    fn heap_access(&mut self, instruction: u8) -> Result<(), Error> {
+        let address = self.get();
+        if instruction == b'g' {
+            let val = self.heap.load(address).map_err(Error::Runtime)?;
+            self.primary.push(val);
+        } else {
+            let val = self.get();
+            self.heap.store(address, val).map_err(Error::Runtime)?;
+        }
+        Ok(())
+    }
+
+    /// Execute an instruction from the decimal extension.
+    fn decimal(&mut self, instruction: u8) -> Result<(), Error> {
+        if instruction == b'n' {
+            let val = self.get();
+            write!(self.output, "{}", val).map_err(|e| Error::Io(e.to_string()))?;
+        } else {
+            self.output.flush().map_err(|e| Error::Io(e.to_string()))?;
+            let value = self.read_number()?;
+            self.push_input(value);
+        }
+        Ok(())
+    }
+
+    /// Run until the program stops, then flush the output.
+    pub fn run(&mut self) -> Result<(), Error> {
+        self.run_traced(|_| {})
+    }
+
+    /// Run as [`run`](Computer::run) does, calling `trace` with the computer
+    /// before each instruction.
+    pub fn run_traced(&mut self, mut trace: impl FnMut(&Self)) -> Result<(), Error> {
+        let mut result = Ok(());
+        while result.is_ok() && self.position < self.tape.len() && self.running {
+            trace(self);
+            result = self.step();
+        }
+        let flushed = self.output.flush().map_err(|e| Error::Io(e.to_string()));
+        result.and(flushed)
+    }
 }
@@ -0,0 +1,228 @@
+//! Compile-time embedding of StackCell programs.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use stackcell_program::program::{BinaryOp, Condition, Op, Program};
+use syn::{parse_macro_input, Lit};
+
+#[cfg(feature = "u32")]
+fn cell_type() -> TokenStream2 {
+    quote!(u32)
+}
+#[cfg(not(feature = "u32"))]
+fn cell_type() -> TokenStream2 {
+    quote!(u8)
+}
+
+fn pop() -> TokenStream2 {
+    quote!(primary.pop().unwrap_or(Wrapping(0)))
+}
+
+fn goto(program: &Program, offset: usize) -> TokenStream2 {
+    if offset >= program.tape.len() {
+        quote!(usize::MAX)
+    } else {
+        quote!(#offset)
+    }
+}
+
+fn binary_op(op: BinaryOp) -> TokenStream2 {
+    let cell = cell_type();
+    match op {
+        BinaryOp::Less => quote!(Wrapping((l < r) as #cell)),
+        BinaryOp::Greater => quote!(Wrapping((l > r) as #cell)),
+        BinaryOp::Equal => quote!(Wrapping((l == r) as #cell)),
+        BinaryOp::Add => quote!(l + r),
+        BinaryOp::Subtract => quote!(l - r),
+        BinaryOp::Multiply => quote!(l * r),
+        // failing as the interpreter does, rather than panicking
+        BinaryOp::Divide => {
+            quote! {
+                match l.0.checked_div(r.0) {
+                    Some(val) => Wrapping(val),
+                    None => return Err("attempt to divide by zero".to_string()),
+                }
+            }
+        },
+        BinaryOp::Modulo => {
+            quote! {
+                match l.0.checked_rem(r.0) {
+                    Some(val) => Wrapping(val),
+                    None => {
+                        return Err(
+                            "attempt to calculate the remainder with a divisor of zero".to_string(),
+                        )
+                    },
+                }
+            }
+        },
+        BinaryOp::Xor => quote!(l ^ r),
+        BinaryOp::And => quote!(l & r),
+        BinaryOp::Or => quote!(l | r),
+    }
+}
+
+fn instruction(op: &Op) -> TokenStream2 {
+    let cell = cell_type();
+    let pop = pop();
+    match op {
+        Op::Push(values) => {
+            quote!(#(primary.push(Wrapping(#values as #cell));)*)
+        },
+        Op::Skip(_) | Op::JumpIf { .. } | Op::Halt => quote!(),
+        Op::Duplicate => {
+            quote! {
+                let val = #pop;
+                primary.push(val);
+                primary.push(val);
+            }
+        },
+        Op::Drop => quote!(#pop;),
+        Op::Store => quote!(cell = #pop;),
+        Op::Load => quote!(primary.push(cell);),
+        Op::Swap => {
+            quote! {
+                let a = #pop;
+                let b = #pop;
+                primary.push(a);
+                primary.push(b);
+            }
+        },
+        Op::SwapStacks => quote!(::std::mem::swap(&mut primary, &mut secondary);),
+        Op::Not => {
+            quote! {
+                let val = #pop;
+                primary.push(Wrapping((val == Wrapping(0)) as #cell));
+            }
+        },
+        Op::Invert => {
+            quote! {
+                let val = #pop;
+                primary.push(!val);
+            }
+        },
+        Op::Binary(op) => {
+            let op = binary_op(*op);
+            quote! {
+                let l = #pop;
+                let r = #pop;
+                primary.push(#op);
+            }
+        },
+        Op::Print => quote!(output.push(#pop.0 as u8);),
+        Op::Input => quote!(primary.push(Wrapping(input.next().unwrap_or(0) as #cell));),
+        // programs are decoded as the classic language, without extensions
+        Op::Extension(_) => unreachable!(),
+    }
+}
+
+fn generate(program: &Program) -> TokenStream2 {
+    let cell = cell_type();
+    let pop = pop();
+    let blocks = program.blocks().into_iter().map(|block| {
+        let start = block[0];
+        let body = block.iter().map(|&offset| {
+            instruction(&program.instructions[offset].as_ref().unwrap().op)
+        });
+        let last = program.instructions[*block.last().unwrap()].as_ref().unwrap();
+        let next = goto(program, last.next);
+        let exit = match &last.op {
+            Op::Halt => quote!(return Ok(output);),
+            Op::JumpIf {
+                when,
+                target: Ok(target),
+            } => {
+                let target = goto(program, *target);
+                let test = match when {
+                    Condition::Zero => quote!(#pop == Wrapping(0)),
+                    Condition::NonZero => quote!(#pop != Wrapping(0)),
+                };
+                quote!(block = if #test { #target } else { #next };)
+            },
+            _ => quote!(block = #next;),
+        };
+        quote! {
+            #start => {
+                #(#body)*
+                #exit
+            },
+        }
+    });
+    quote! {
+        {
+            #[allow(unused, clippy::all)]
+            fn stackcell_program(
+                input: &[u8],
+            ) -> ::std::result::Result<::std::vec::Vec<u8>, ::std::string::String> {
+                use ::std::num::Wrapping;
+                let mut input = input.iter().copied();
+                let mut output = ::std::vec::Vec::new();
+                let mut primary: ::std::vec::Vec<Wrapping<#cell>> = ::std::vec::Vec::new();
+                let mut secondary: ::std::vec::Vec<Wrapping<#cell>> = ::std::vec::Vec::new();
+                let mut cell: Wrapping<#cell> = Wrapping(0);
+                let mut block: usize = 0;
+                loop {
+                    match block {
+                        #(#blocks)*
+                        _ => return Ok(output),
+                    }
+                }
+            }
+            stackcell_program
+        }
+    }
+}
+
+/// Compile a StackCell program into a function which takes the program's
+/// input as bytes and returns everything it printed, or the error which
+/// stopped it (such as `attempt to divide by zero`, as the interpreter says).
+///
+/// The program is decoded and checked when the macro is expanded, so any
+/// reachable invalid instruction or unmatched loop bracket is a compile error,
+/// and the generated function is specialised Rust code rather than a call into
+/// the interpreter. Unlike the interpreter, printed values are returned as raw
+/// bytes (truncated to `u8`), and reading past the end of the input pushes 0.
+///
+/// ```
+/// use stackcell_macros::stackcell;
+///
+/// let truth_machine = stackcell!("'0@-:[:'0+;:]'0+;.");
+/// assert_eq!(truth_machine(b"0").unwrap(), b"0");
+///
+/// let divide = stackcell!("#00'a/;");
+/// assert_eq!(divide(b"").unwrap_err(), "attempt to divide by zero");
+/// ```
+///
+/// ```compile_fail
+/// use stackcell_macros::stackcell;
+///
+/// let unmatched = stackcell!(":[;:");
+/// ```
+///
+/// ```compile_fail
+/// use stackcell_macros::stackcell;
+///
+/// let bad_hex = stackcell!("#4G;");
+/// ```
+#[proc_macro]
+pub fn stackcell(input: TokenStream) -> TokenStream {
+    let literal = parse_macro_input!(input as Lit);
+    let (tape, span) = match &literal {
+        Lit::Str(s) => (s.value().into_bytes(), s.span()),
+        Lit::ByteStr(s) => (s.value(), s.span()),
+        _ => {
+            return syn::Error::new(
+                Span::call_site(),
+                "Expected a string or byte string literal",
+            )
+            .to_compile_error()
+            .into();
+        },
+    };
+    let program = Program::new(&tape);
+    if let Some(e) = program.errors().into_iter().next() {
+        return syn::Error::new(span, e).to_compile_error().into();
+    }
+    generate(&program).into()
+}
@@ -0,0 +1,66 @@
+use stackcell_macros::stackcell;
+
+#[test]
+fn samples() {
+    let cat = stackcell!("@:[;@:]");
+    assert_eq!(cat(b"Hello, cat!\n").unwrap(), b"Hello, cat!\n");
+
+    let hello = stackcell!("\"\n!dlrow ,olleH\":[;:]");
+    assert_eq!(hello(b"").unwrap(), b"Hello, world!\n");
+
+    let tac = stackcell!("@:[@:]`:[;:]");
+    assert_eq!(tac(b"reversed").unwrap(), b"desrever");
+
+    let tac_line = stackcell!("(@:#0A-)`:[;:]#0A;.");
+    assert_eq!(tac_line(b"ab").unwrap(), b"\n");
+
+    let truth_machine = stackcell!("'0@-:[:'0+;:]'0+;.");
+    assert_eq!(truth_machine(b"0").unwrap(), b"0");
+
+    let quine = stackcell!(
+        "\"#22;:[{X}X:]X:[:{X}X;:]#22;X:[{X}X:]X:[;:]\"#22;:[{X}X:]X:[:{X}X;:]#22;X:[{X}X:]X:[;:]"
+    );
+    assert_eq!(
+        quine(b"").unwrap(),
+        &b"\"#22;:[{X}X:]X:[:{X}X;:]#22;X:[{X}X:]X:[;:]\"#22;:[{X}X:]X:[:{X}X;:]#22;X:[{X}X:]X:[;:]"[..]
+    );
+
+    let quine_family = stackcell!(b"\"[#22;:[{X}X:]X:[;:]#22;#3A;#5D;7\":]");
+    assert_eq!(
+        quine_family(b"").unwrap(),
+        &b"\"[#22;:[{X}X:]X:[;:]#22;#3A;#5D;7\":]"[..]
+    );
+}
+
+#[test]
+fn wrapping_arithmetic() {
+    let program = stackcell!("#FF#02+;#00#01-;#10#10*;'a~;");
+    assert_eq!(program(b"").unwrap(), [0x01, 0x01, 0x00, 0x9E]);
+}
+
+#[test]
+fn jumps_into_data() {
+    // skip into the middle of a string literal
+    let program = stackcell!("1\"'A;.");
+    assert_eq!(program(b"").unwrap(), b"A");
+    // skip off the end of the tape
+    let program = stackcell!("'A;9");
+    assert_eq!(program(b"").unwrap(), b"A");
+    // unmatched brackets are fine as long as they can't be reached
+    let program = stackcell!("'a;.]");
+    assert_eq!(program(b"").unwrap(), b"a");
+}
+
+#[test]
+fn division_by_zero() {
+    // is an error, as in the interpreter, rather than a panic
+    let divide = stackcell!("#00'a/;");
+    assert_eq!(divide(b"").unwrap_err(), "attempt to divide by zero");
+    let modulo = stackcell!("'a;#00'a%;");
+    assert_eq!(
+        modulo(b"").unwrap_err(),
+        "attempt to calculate the remainder with a divisor of zero"
+    );
+    let program = stackcell!("#03'a/;#03'a%;");
+    assert_eq!(program(b"").unwrap(), [32, 1]);
+}
@@ -0,0 +1,159 @@
+//! Dialects: the classic language, plus whichever extensions a program asks
+//! for.
+//!
+//! Extensions give meanings to bytes which are unknown instructions in the
+//! classic language, so a program only gets them by asking, either with a
+//! header line such as `#!stackcell 1 decimal` or on the command line. Without
+//! either, every program means what it always has.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// The start of a header line.
+pub const HEADER: &str = "#!stackcell";
+
+/// The version of the language described by this crate, which is the only
+/// one a header can ask for.
+pub const VERSION: u32 = 1;
+
+/// A set of instructions which can be added to the classic language.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Extension {
+    /// `n` and `N`: print and read decimal numbers.
+    Decimal,
+    /// `D`, `C` and `R`: define, call and return from subroutines.
+    Calls,
+    /// `g` and `p`: load from and store to the heap.
+    Heap,
+}
+
+impl Extension {
+    pub const ALL: &'static [Extension] = &[Extension::Decimal, Extension::Calls, Extension::Heap];
+
+    /// The name used for this extension in headers, assembly and on the
+    /// command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Extension::Decimal => "decimal",
+            Extension::Calls => "calls",
+            Extension::Heap => "heap",
+        }
+    }
+
+    /// The bytes this extension gives meanings to, none of which mean
+    /// anything in the classic language or any other extension.
+    pub fn instructions(self) -> &'static [u8] {
+        match self {
+            Extension::Decimal => b"nN",
+            Extension::Calls => b"DCR",
+            Extension::Heap => b"gp",
+        }
+    }
+}
+
+impl fmt::Display for Extension {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Extension {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Extension, String> {
+        Extension::ALL
+            .iter()
+            .copied()
+            .find(|extension| extension.name() == name)
+            .ok_or_else(|| format!("Unknown extension {:?}", name))
+    }
+}
+
+/// The extensions enabled for a program. The default is the classic language,
+/// with none.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Dialect {
+    /// Sorted, without duplicates.
+    extensions: Vec<Extension>,
+}
+
+impl Dialect {
+    pub fn new(extensions: impl IntoIterator<Item = Extension>) -> Dialect {
+        let mut dialect = Dialect::default();
+        for extension in extensions {
+            dialect.enable(extension);
+        }
+        dialect
+    }
+
+    pub fn enable(&mut self, extension: Extension) {
+        if let Err(i) = self.extensions.binary_search(&extension) {
+            self.extensions.insert(i, extension);
+        }
+    }
+
+    pub fn extensions(&self) -> &[Extension] {
+        &self.extensions
+    }
+
+    pub fn is_classic(&self) -> bool {
+        self.extensions.is_empty()
+    }
+
+    pub fn has(&self, extension: Extension) -> bool {
+        self.extensions.contains(&extension)
+    }
+
+    /// The enabled extension which gives `instruction` its meaning, if any.
+    pub fn extension(&self, instruction: u8) -> Option<Extension> {
+        self.extensions
+            .iter()
+            .copied()
+            .find(|extension| extension.instructions().contains(&instruction))
+    }
+
+    /// Split the header off the start of `file`, returning the dialect it asks
+    /// for and the program after it. Files without a header are classic.
+    ///
+    /// Offsets into a program (in errors, and when disassembling) count from
+    /// the end of its header.
+    pub fn parse(file: &[u8]) -> Result<(Dialect, &[u8]), String> {
+        if !file.starts_with(HEADER.as_bytes()) {
+            return Ok((Dialect::default(), file));
+        }
+        let (line, program) = match file.iter().position(|&byte| byte == b'\n') {
+            Some(end) => (&file[..end], &file[end + 1..]),
+            None => (file, &file[file.len()..]),
+        };
+        let line = std::str::from_utf8(&line[HEADER.len()..])
+            .map_err(|_| "Invalid header".to_string())?;
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some(version) if version == VERSION.to_string() => {},
+            Some(version) => {
+                return Err(format!(
+                    "Unsupported version {:?} in header (expected {})",
+                    version, VERSION
+                ))
+            },
+            None => return Err(format!("Missing version in header (expected {})", VERSION)),
+        }
+        let extensions = words.map(str::parse).collect::<Result<Vec<_>, _>>()?;
+        Ok((Dialect::new(extensions), program))
+    }
+
+    /// The header line (with its newline) which asks for this dialect, or
+    /// nothing for the classic language.
+    pub fn header(&self) -> String {
+        if self.is_classic() {
+            return String::new();
+        }
+        let mut header = format!("{} {}", HEADER, VERSION);
+        for extension in &self.extensions {
+            header.push(' ');
+            header.push_str(extension.name());
+        }
+        header.push('\n');
+        header
+    }
+}
@@ -0,0 +1,5 @@
+//! Decoding StackCell programs, shared by the interpreter and the
+//! `stackcell!` macro.
+
+pub mod dialect;
+pub mod program;
@@ -0,0 +1,479 @@
+use crate::dialect::{Dialect, Extension};
+
+pub fn unhex(c: u8) -> Result<u8, String> {
+    match c {
+        b'0'..=b'9' => Ok(c - b'0'),
+        b'a'..=b'f' => Ok(c - b'a' + 10),
+        b'A'..=b'F' => Ok(c - b'A' + 10),
+        _ => Err(format!("Invalid hex character: {}", c)),
+    }
+}
+
+/// The condition under which a conditional jump is taken, tested against the
+/// value popped from the top of the primary stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Zero,
+    NonZero,
+}
+
+/// A binary operation on the top two values of the primary stack. The top
+/// value is the left hand side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Less,
+    Greater,
+    Equal,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Xor,
+    And,
+    Or,
+}
+
+/// An instruction added by an [`Extension`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtensionOp {
+    /// `n`: pop a value and print it in decimal
+    PrintNumber,
+    /// `N`: read a decimal number and push it
+    InputNumber,
+    /// `D` and a label: mark the start of a subroutine, doing nothing when
+    /// executed
+    Define(u8),
+    /// `C` and a label: push [`Instruction::next`] to the return stack, and
+    /// continue at `target`, just after the `D` with the same label. The target
+    /// is an error if there is no such `D`.
+    Call {
+        label: u8,
+        target: Result<usize, String>,
+    },
+    /// `R`: continue at the offset popped from the return stack
+    Return,
+    /// `g`: pop an address, and push the heap cell at it
+    HeapLoad,
+    /// `p`: pop an address, then a value, and store the value in the heap cell
+    /// at the address
+    HeapStore,
+}
+
+impl ExtensionOp {
+    pub fn extension(&self) -> Extension {
+        match self {
+            ExtensionOp::PrintNumber | ExtensionOp::InputNumber => Extension::Decimal,
+            ExtensionOp::Define(_) | ExtensionOp::Call { .. } | ExtensionOp::Return => {
+                Extension::Calls
+            },
+            ExtensionOp::HeapLoad | ExtensionOp::HeapStore => Extension::Heap,
+        }
+    }
+}
+
+/// What an instruction does, with every operand (including jump targets)
+/// resolved ahead of time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    /// `'`, `"` and `#`: push these bytes, in order, to the primary stack
+    Push(Vec<u8>),
+    /// `1` to `9`: do nothing; the skip is folded into [`Instruction::next`]
+    Skip(u8),
+    /// `[`, `]`, `(`, `)` and `?`: pop a value, and continue at `target`
+    /// instead of [`Instruction::next`] if it satisfies `when`. The target is
+    /// an error if the matching bracket doesn't exist; this is only reported
+    /// if the jump is taken.
+    JumpIf {
+        when: Condition,
+        target: Result<usize, String>,
+    },
+    Halt,
+    Duplicate,
+    Drop,
+    Store,
+    Load,
+    Swap,
+    SwapStacks,
+    Not,
+    Invert,
+    Binary(BinaryOp),
+    Print,
+    Input,
+    Extension(ExtensionOp),
+}
+
+/// An instruction decoded from a particular offset into the tape.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub op: Op,
+    /// The offset of the first byte of this instruction.
+    pub start: usize,
+    /// The offset execution continues from, unless a jump is taken. May be
+    /// past the end of the tape, which halts the program.
+    pub next: usize,
+}
+
+impl Instruction {
+    /// Every offset control may reach after executing this instruction.
+    ///
+    /// A call is taken to continue both at its target and after it, where the
+    /// subroutine returns to, so a return doesn't continue anywhere itself.
+    pub fn successors(&self) -> Vec<usize> {
+        match &self.op {
+            Op::Halt | Op::Extension(ExtensionOp::Return) => vec![],
+            Op::JumpIf {
+                target: Ok(target),
+                ..
+            }
+            | Op::Extension(ExtensionOp::Call {
+                target: Ok(target),
+                ..
+            }) => vec![self.next, *target],
+            _ => vec![self.next],
+        }
+    }
+}
+
+/// A `.cel` tape with an instruction decoded at every offset, in some dialect.
+///
+/// Since digit skips, `?` and loops all move by bytes rather than by
+/// instructions, control can land in the middle of what would otherwise be
+/// read as a single instruction (for example inside a string literal), so
+/// every offset is decoded independently. Decoding failures are kept per
+/// offset and only matter if execution actually reaches them, exactly like the
+/// interpreter.
+pub struct Program {
+    pub tape: Vec<u8>,
+    pub dialect: Dialect,
+    pub definitions: Definitions,
+    pub instructions: Vec<Result<Instruction, String>>,
+}
+
+impl Program {
+    /// Decode `tape` as the classic language.
+    pub fn new(tape: &[u8]) -> Program {
+        Program::with_dialect(tape, Dialect::default())
+    }
+
+    pub fn with_dialect(tape: &[u8], dialect: Dialect) -> Program {
+        let definitions = Definitions::new(tape);
+        Program {
+            tape: tape.to_vec(),
+            instructions: (0..tape.len())
+                .map(|i| decode(tape, i, &dialect, &definitions))
+                .collect(),
+            dialect,
+            definitions,
+        }
+    }
+
+    /// Decode the contents of a `.cel` file, in the dialect its header asks
+    /// for (see [`Dialect::parse`]).
+    pub fn parse(file: &[u8]) -> Result<Program, String> {
+        let (dialect, tape) = Dialect::parse(file)?;
+        Ok(Program::with_dialect(tape, dialect))
+    }
+
+    /// The offsets execution can reach from the start of the tape, in
+    /// ascending order. Offsets past the end of the tape are not included.
+    pub fn reachable(&self) -> Vec<usize> {
+        let mut seen = vec![false; self.tape.len()];
+        let mut queue = vec![0];
+        while let Some(offset) = queue.pop() {
+            if offset >= self.tape.len() || seen[offset] {
+                continue;
+            }
+            seen[offset] = true;
+            if let Ok(instruction) = &self.instructions[offset] {
+                queue.extend(instruction.successors());
+            }
+        }
+        (0..self.tape.len()).filter(|&i| seen[i]).collect()
+    }
+
+    /// Split the reachable offsets into basic blocks: runs of instructions
+    /// which can only be entered at their first offset, and which can only
+    /// branch (or fail) at their last. Blocks are sorted by their first offset,
+    /// which is always 0 for the first block of a non-empty program.
+    pub fn blocks(&self) -> Vec<Vec<usize>> {
+        let reachable = self.reachable();
+        let mut predecessors = vec![0; self.tape.len()];
+        let mut is_leader = vec![false; self.tape.len()];
+        if !self.tape.is_empty() {
+            is_leader[0] = true;
+        }
+        for &offset in &reachable {
+            if let Ok(instruction) = &self.instructions[offset] {
+                let branches = matches!(instruction.op, Op::JumpIf { .. });
+                for successor in instruction.successors() {
+                    if successor < self.tape.len() {
+                        predecessors[successor] += 1;
+                        is_leader[successor] |= branches;
+                    }
+                }
+            }
+        }
+        let mut blocks = vec![];
+        for &offset in &reachable {
+            if !is_leader[offset] && predecessors[offset] == 1 {
+                continue;
+            }
+            let mut block = vec![offset];
+            let mut current = offset;
+            while let Ok(instruction) = &self.instructions[current] {
+                let next = instruction.next;
+                if matches!(instruction.op, Op::JumpIf { .. } | Op::Halt)
+                    || next >= self.tape.len()
+                    || is_leader[next]
+                    || predecessors[next] != 1
+                {
+                    break;
+                }
+                block.push(next);
+                current = next;
+            }
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    /// Every error the program could hit: instructions it can reach which fail
+    /// to decode, loops it can reach which have no matching bracket, and calls
+    /// it can reach to subroutines which aren't defined.
+    pub fn errors(&self) -> Vec<String> {
+        let mut errors = vec![];
+        for offset in self.reachable() {
+            match &self.instructions[offset] {
+                Ok(instruction) => {
+                    match &instruction.op {
+                        Op::JumpIf { target: Err(_), .. } => {
+                            errors.push(format!(
+                                "Unmatched {:?} at offset {}",
+                                self.tape[offset] as char, offset
+                            ))
+                        },
+                        Op::Extension(ExtensionOp::Call {
+                            target: Err(e), ..
+                        }) => errors.push(format!("{} (at offset {})", e, offset)),
+                        _ => {},
+                    }
+                },
+                Err(e) => errors.push(format!("{} (at offset {})", e, offset)),
+            }
+        }
+        errors
+    }
+}
+
+fn read(tape: &[u8], position: usize) -> Result<u8, String> {
+    tape.get(position)
+        .copied()
+        .ok_or_else(|| "Indexed out of bounds!".to_string())
+}
+
+/// Find the offset just after the `close` matching an `open` whose body starts
+/// at `position`.
+fn find_matching_close(
+    tape: &[u8],
+    mut position: usize,
+    open: u8,
+    close: u8,
+) -> Result<usize, String> {
+    let mut depth = 0;
+    while depth != 0 || read(tape, position)? != close {
+        if read(tape, position)? == open {
+            depth += 1;
+        } else if read(tape, position)? == close {
+            depth -= 1;
+        }
+        position += 1;
+    }
+    Ok(position + 1)
+}
+
+/// Find the offset just after the `open` matching the `close` at `position`.
+fn find_matching_open(
+    tape: &[u8],
+    position: usize,
+    open: u8,
+    close: u8,
+) -> Result<usize, String> {
+    let mut depth = 0;
+    let mut position = position
+        .checked_sub(1)
+        .ok_or_else(|| "Indexed out of bounds!".to_string())?;
+    while depth != 0 || read(tape, position)? != open {
+        if read(tape, position)? == close {
+            depth += 1;
+        } else if read(tape, position)? == open {
+            depth -= 1;
+        }
+        position = position
+            .checked_sub(1)
+            .ok_or_else(|| "Indexed out of bounds!".to_string())?;
+    }
+    Ok(position + 1)
+}
+
+/// Where each subroutine of a tape starts.
+///
+/// A subroutine is defined by the first `D` with its label which is an
+/// instruction when the tape is read from start to end (as the disassembler
+/// reads it, with skipped bytes as instructions in their own right), so a `D`
+/// inside a literal such as `"aD"`, or which is the label of another `D` or
+/// `C`, doesn't define anything.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Definitions([Option<usize>; 256]);
+
+impl Definitions {
+    pub fn new(tape: &[u8]) -> Definitions {
+        // other extensions' instructions are a byte long, as unknown ones are
+        let dialect = Dialect::new([Extension::Calls]);
+        let mut definitions = Definitions([None; 256]);
+        let mut offset = 0;
+        while offset < tape.len() {
+            // the targets of calls don't matter, just where each one ends
+            offset = match decode(tape, offset, &dialect, &definitions) {
+                Ok(Instruction {
+                    op: Op::Extension(ExtensionOp::Define(label)),
+                    next,
+                    ..
+                }) => {
+                    definitions.0[label as usize].get_or_insert(next);
+                    next
+                },
+                Ok(Instruction { op: Op::Skip(_), .. }) | Err(_) => offset + 1,
+                Ok(instruction) => instruction.next,
+            };
+        }
+        definitions
+    }
+
+    /// The offset just after the definition of the subroutine labelled
+    /// `label`.
+    pub fn find(&self, label: u8) -> Result<usize, String> {
+        self.0[label as usize].ok_or_else(|| format!("Undefined subroutine {:?}", label as char))
+    }
+}
+
+/// Decode the instruction starting at `start` in `dialect`, mirroring
+/// the interpreter's `Computer::step`, with calls going to the
+/// subroutines in `definitions`.
+pub fn decode(
+    tape: &[u8],
+    start: usize,
+    dialect: &Dialect,
+    definitions: &Definitions,
+) -> Result<Instruction, String> {
+    let instruction = read(tape, start)?;
+    let mut next = start + 1;
+    let op = match instruction {
+        b'\'' => {
+            let val = read(tape, next)?;
+            next += 1;
+            Op::Push(vec![val])
+        },
+        b'"' => {
+            let mut vals = vec![];
+            while read(tape, next)? != b'"' {
+                vals.push(read(tape, next)?);
+                next += 1;
+            }
+            next += 1;
+            Op::Push(vals)
+        },
+        b'#' => {
+            let d0 = unhex(read(tape, next)?)?;
+            let d1 = unhex(read(tape, next + 1)?)?;
+            next += 2;
+            Op::Push(vec![d0 * 16 + d1])
+        },
+        b'1'..=b'9' => {
+            let distance = unhex(instruction)?;
+            next += distance as usize;
+            Op::Skip(distance)
+        },
+        b'[' => {
+            Op::JumpIf {
+                when: Condition::Zero,
+                target: find_matching_close(tape, next, b'[', b']'),
+            }
+        },
+        b']' => {
+            Op::JumpIf {
+                when: Condition::NonZero,
+                target: find_matching_open(tape, start, b'[', b']'),
+            }
+        },
+        b'(' => {
+            Op::JumpIf {
+                when: Condition::NonZero,
+                target: find_matching_close(tape, next, b'(', b')'),
+            }
+        },
+        b')' => {
+            Op::JumpIf {
+                when: Condition::Zero,
+                target: find_matching_open(tape, start, b'(', b')'),
+            }
+        },
+        b'?' => {
+            Op::JumpIf {
+                when: Condition::Zero,
+                target: Ok(next + 1),
+            }
+        },
+        b'.' => Op::Halt,
+        b':' => Op::Duplicate,
+        b'`' => Op::Drop,
+        b'{' => Op::Store,
+        b'}' => Op::Load,
+        b'x' => Op::Swap,
+        b'X' => Op::SwapStacks,
+        b'!' => Op::Not,
+        b'<' => Op::Binary(BinaryOp::Less),
+        b'>' => Op::Binary(BinaryOp::Greater),
+        b'=' => Op::Binary(BinaryOp::Equal),
+        b'+' => Op::Binary(BinaryOp::Add),
+        b'-' => Op::Binary(BinaryOp::Subtract),
+        b'*' => Op::Binary(BinaryOp::Multiply),
+        b'/' => Op::Binary(BinaryOp::Divide),
+        b'%' => Op::Binary(BinaryOp::Modulo),
+        b'^' => Op::Binary(BinaryOp::Xor),
+        b'&' => Op::Binary(BinaryOp::And),
+        b'|' => Op::Binary(BinaryOp::Or),
+        b'~' => Op::Invert,
+        b';' => Op::Print,
+        b'@' => Op::Input,
+        _ => {
+            match dialect.extension(instruction) {
+                Some(Extension::Decimal) if instruction == b'n' => {
+                    Op::Extension(ExtensionOp::PrintNumber)
+                },
+                Some(Extension::Decimal) => Op::Extension(ExtensionOp::InputNumber),
+                Some(Extension::Calls) if instruction == b'R' => {
+                    Op::Extension(ExtensionOp::Return)
+                },
+                Some(Extension::Calls) => {
+                    let label = read(tape, next)?;
+                    next += 1;
+                    if instruction == b'D' {
+                        Op::Extension(ExtensionOp::Define(label))
+                    } else {
+                        Op::Extension(ExtensionOp::Call {
+                            label,
+                            target: definitions.find(label),
+                        })
+                    }
+                },
+                Some(Extension::Heap) if instruction == b'g' => {
+                    Op::Extension(ExtensionOp::HeapLoad)
+                },
+                Some(Extension::Heap) => Op::Extension(ExtensionOp::HeapStore),
+                None => return Err(format!("Unknown instruction: {:?}", instruction as char)),
+            }
+        },
+    };
+    Ok(Instruction { op, start, next })
+}
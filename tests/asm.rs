@@ -0,0 +1,619 @@
+use stackcell::asm;
+use stackcell::asm::ast::{Mnemonic, Operand, Spanned, Statement, Target, Value};
+use stackcell::asm::diagnostic::Diagnostic;
+use stackcell::asm::source::Sources;
+use stackcell::program::Program;
+use stackcell::source_map::SourceMap;
+use stackcell::types::Computer;
+
+fn round_trip(tape: &[u8]) {
+    let program = Program::parse(tape).unwrap();
+    for offsets in [false, true] {
+        let source = asm::disasm::disassemble(&program, offsets).unwrap();
+        assert_eq!(
+            asm::assemble(&source).unwrap().as_bytes(),
+            tape,
+            "disassembly of {:?} doesn't round trip:\n{}",
+            String::from_utf8_lossy(tape),
+            source
+        );
+    }
+    // and it's formatted, apart from the alignment of offsets
+    let source = asm::disasm::disassemble(&program, false).unwrap();
+    assert_eq!(asm::fmt::format(&source).unwrap(), source);
+}
+
+/// Assemble `source` as the file `path`, returning the files read with the
+/// result.
+fn assemble_file(path: &str, source: &str) -> (Sources, Result<String, Vec<Diagnostic>>) {
+    let mut sources = Sources::default();
+    let file = sources.add(path, source);
+    let result = asm::assemble_file(&mut sources, file);
+    (sources, result)
+}
+
+/// The message of the only error in assembling `source`.
+fn error(source: &str) -> String {
+    let errors = asm::assemble(source).unwrap_err();
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    errors[0].message.clone()
+}
+
+#[test]
+fn disassembly_round_trips() {
+    for name in &[
+        "cat",
+        "error_quine",
+        "hello",
+        "quine",
+        "quine_family",
+        "tac",
+        "tac_line",
+        "truth_machine",
+    ] {
+        round_trip(&std::fs::read(format!("samples/{}.cel", name)).unwrap());
+    }
+    round_trip(b"'a'''\"'\"'#0G#ff#01+3:?X;x");
+    round_trip(b"#01[\"!ko\":[;:]");
+    round_trip("'é;\"ü\"".as_bytes());
+    round_trip(b"\"a\\\t\n\x01\";");
+    round_trip(b"#!stackcell 1 decimal\nNn'n;");
+    round_trip(b"#!stackcell 1 decimal calls\nCb.DbnR");
+    // which runs into its first subroutine
+    round_trip(b"#!stackcell 1 calls\nCb'aDbR;CaDaR");
+    round_trip(b"#!stackcell 1 heap\n'a#00p#00g;");
+}
+
+#[test]
+fn disassembly() {
+    let disassemble = |tape: &[u8]| asm::disasm::disassemble(&Program::new(tape), false);
+    assert_eq!(
+        disassemble(b"@:[;@:]").unwrap(),
+        "in\ndup\nwhile nonzero\n    out\n    in\n    dup\nnext nonzero\n"
+    );
+    assert_eq!(disassemble(b"\"cba\"5.").unwrap(), "push \"abc\"\njmp 5\nraw \".\"\n");
+    assert_eq!(disassemble(b";\"a\\b").unwrap(), "out\nraw \"\\\"a\\\\b\"\n");
+    assert_eq!(
+        disassemble(b"'\xFF").unwrap_err(),
+        "Byte 0xff at offset 1 can't be written in assembly"
+    );
+    // extension instructions are only recognised in their dialects
+    assert_eq!(disassemble(b"Nn").unwrap(), "raw \"Nn\"\n");
+    let program = Program::parse(b"#!stackcell 1 decimal\nNn").unwrap();
+    assert_eq!(
+        asm::disasm::disassemble(&program, true).unwrap(),
+        "dialect decimal\ninnum   ; 0\noutnum  ; 1\n"
+    );
+}
+
+#[test]
+fn formatting() {
+    // including converting comments which start with `#`
+    assert_eq!(
+        asm::fmt::format(
+            "  push 'a' dup # two of them\n\n\n\nwhile\nnonzero out   ; print\n  \
+             # and again\nnext nonzero swap stacks"
+        )
+        .unwrap(),
+        "push 'a'\ndup  ; two of them\n\nwhile nonzero\n    out  ; print\n    ; and \
+         again\nnext nonzero\nswap stacks\n"
+    );
+    assert_eq!(
+        asm::fmt::format("in jz empty out empty: push 'n'").unwrap(),
+        "in\njz empty\nout\nempty:\npush 'n'\n"
+    );
+    assert_eq!(
+        asm::fmt::format("push 'a' foo").unwrap_err()[0].message,
+        "Found invalid token \"foo\""
+    );
+}
+
+#[test]
+fn parsing() {
+    let mut sources = Sources::default();
+    let file = sources.add("", "push 'a'\nadd p1 $02  # comment\njz end");
+    let statements = asm::ast::parse(&mut sources, &[file]).unwrap();
+    assert_eq!(
+        statements,
+        [
+            Spanned {
+                node: Statement::Push(Value::Literal("'a".to_string())),
+                span: 0..8,
+                expanded_from: vec![],
+            },
+            Spanned {
+                node: Statement::Op {
+                    mnemonic: Mnemonic::Add,
+                    operands: vec![
+                        Spanned {
+                            node: Operand::Target(Target::P1),
+                            span: 13..15,
+                            expanded_from: vec![],
+                        },
+                        Spanned {
+                            node: Operand::Literal("#02".to_string()),
+                            span: 16..19,
+                            expanded_from: vec![],
+                        },
+                    ],
+                },
+                span: 9..19,
+                expanded_from: vec![],
+            },
+            Spanned {
+                node: Statement::JumpIfZero("end".to_string()),
+                span: 31..37,
+                expanded_from: vec![],
+            },
+        ]
+    );
+    // lowering is where statements are checked against each other
+    assert_eq!(
+        asm::lower(&statements).unwrap_err()[0].message,
+        "Label \"end\" is jumped to but never defined"
+    );
+}
+
+#[test]
+fn assembly() {
+    assert_eq!(asm::assemble("push 'a' out").unwrap(), "'a;");
+    assert_eq!(asm::assemble("sub s1 p1").unwrap(), "X}x{X}X{X-");
+    assert_eq!(asm::assemble("push \"hi\" raw \"..\"").unwrap(), "\"ih\"..");
+    assert_eq!(
+        asm::assemble("dialect decimal innum outnum p2").unwrap(),
+        "#!stackcell 1 decimal\nNxn"
+    );
+    assert_eq!(
+        error("innum"),
+        "\"innum\" needs the decimal extension (add `dialect decimal` first)"
+    );
+    assert_eq!(
+        error("in dialect decimal"),
+        "The dialect must be given before any code"
+    );
+    // single letter names label themselves, and others get what's left
+    assert_eq!(
+        asm::assemble("dialect calls call greet call a def greet ret def a ret").unwrap(),
+        "#!stackcell 1 calls\nCbCa.DbRDaR"
+    );
+    // the program stops before its first subroutine, unless it can't run into
+    // it anyway
+    assert_eq!(asm::assemble("dialect calls def f ret").unwrap(), "#!stackcell 1 calls\n.DfR");
+    assert_eq!(
+        asm::assemble("dialect calls call f jmp end def f ret end: call f raw \".\" def g ret")
+            .unwrap(),
+        "#!stackcell 1 calls\nCf3DfRCf.DgR"
+    );
+    assert_eq!(
+        error("dialect calls call greet"),
+        "Subroutine \"greet\" is called but never defined"
+    );
+    assert_eq!(
+        error("dialect calls def a def a"),
+        "Subroutine \"a\" is defined twice"
+    );
+    // the operand of peek and poke is the address
+    assert_eq!(
+        asm::assemble("dialect heap push 'a' poke $03 peek $03 out").unwrap(),
+        "#!stackcell 1 heap\n'a#03p#03g;"
+    );
+    assert_eq!(error("greet"), "Found invalid token \"greet\"");
+    assert_eq!(
+        error("jmp"),
+        "Expected jump distance or label but found EOF"
+    );
+    // jumps to labels are filled in once everything after them is assembled
+    assert_eq!(
+        asm::assemble("in jz is_zero push 'n' jmp end is_zero: push 'z' end: out").unwrap(),
+        "@?13'n2'z;"
+    );
+    assert_eq!(
+        error("jmp a push \"far too far\" a:"),
+        "Label \"a\" is 13 bytes past the jump to it, but a jump can skip at most 9"
+    );
+    assert_eq!(
+        error("a: jmp a"),
+        "Label \"a\" is before the jump to it, but jumps only go forward"
+    );
+    assert_eq!(
+        error("jmp a out"),
+        "Label \"a\" is jumped to but never defined"
+    );
+    assert_eq!(error("a: a:"), "Label \"a\" is defined twice");
+}
+
+#[test]
+fn diagnostics() {
+    assert_eq!(error("push #300"), "Decimal literal #300 is out of range (at most 255)");
+    assert_eq!(error("out #256"), "Decimal literal #256 is out of range (at most 255)");
+    // every error is found, in the order they're in the source
+    let errors = asm::assemble("push #300 loop foo greet out").unwrap_err();
+    let messages: Vec<&str> = errors.iter().map(|error| error.message.as_str()).collect();
+    assert_eq!(
+        messages,
+        [
+            "Decimal literal #300 is out of range (at most 255)",
+            "Expected loop condition but found \"foo\"",
+            "Found invalid token \"greet\"",
+        ]
+    );
+    // statements are only checked against each other once they all parse
+    let source = "push 'a'\n\tcall greet jmp end\ninnum";
+    let (sources, result) = assemble_file("greet.casm", source);
+    let errors = result.unwrap_err();
+    let messages: Vec<&str> = errors.iter().map(|error| error.message.as_str()).collect();
+    assert_eq!(
+        messages,
+        [
+            "\"call\" needs the calls extension (add `dialect calls` first)",
+            "Subroutine \"greet\" is called but never defined",
+            "Label \"end\" is jumped to but never defined",
+            "\"innum\" needs the decimal extension (add `dialect decimal` first)",
+        ]
+    );
+    assert_eq!(errors[1].position(&sources), ("greet.casm", 2, 2));
+    assert_eq!(
+        errors[1].render(&sources),
+        "greet.casm:2:2: Subroutine \"greet\" is called but never defined\n  |\n2 | \tcall \
+         greet jmp end\n  | \t^^^^^^^^^^\n"
+    );
+    // an error at the end of the source points at what it should have followed
+    let (sources, result) = assemble_file("swap.casm", "out\nswap");
+    let errors = result.unwrap_err();
+    assert_eq!(errors[0].message, "Expected swap type but found EOF");
+    assert_eq!(errors[0].position(&sources), ("swap.casm", 2, 1));
+}
+
+#[test]
+fn macros() {
+    assert_eq!(
+        asm::assemble("macro twice(code) code code endmacro twice(push 'a' out)").unwrap(),
+        "'a;'a;"
+    );
+    // labels in a macro are local to each expansion, but labels passed to it
+    // aren't
+    assert_eq!(
+        asm::assemble("macro maybe(c) jz skip push c out skip: endmacro in maybe('y') in maybe('n')")
+            .unwrap(),
+        "@?13'y;@?13'n;"
+    );
+    assert_eq!(
+        asm::assemble("macro skip_if(target) jz target endmacro in skip_if(end) out end:").unwrap(),
+        "@?11;"
+    );
+    // macros can call each other, and arguments can be calls themselves
+    assert_eq!(
+        asm::assemble(
+            "macro print(c) push c out endmacro macro both(a, b) print(a) b endmacro \
+             both('x', print('y'))"
+        )
+        .unwrap(),
+        "'x;'y;"
+    );
+    // errors about local labels name them as they're written
+    assert_eq!(
+        error("macro far() jmp end push \"far too far\" end: endmacro far()"),
+        "Label \"end\" is 13 bytes past the jump to it, but a jump can skip at most 9"
+    );
+    assert_eq!(error("macro twice() a: a: endmacro twice()"), "Label \"a\" is defined twice");
+    assert_eq!(error("m(1)"), "Macro \"m\" is not defined");
+    assert_eq!(
+        error("macro m(a, b) endmacro m('a')"),
+        "Macro \"m\" takes 2 arguments but was given 1"
+    );
+    assert_eq!(error("macro m() m() endmacro m()"), "Macro \"m\" calls itself");
+    assert_eq!(error("macro m() out"), "Macro \"m\" is never closed with endmacro");
+    assert_eq!(error("macro m(a, a) endmacro"), "Parameter \"a\" is given twice");
+    assert_eq!(error("macro m() endmacro macro m() endmacro"), "Macro \"m\" is defined twice");
+    // errors in expanded code say where it was expanded from
+    let source = "macro read() innum endmacro\nmacro twice() read() read() endmacro\ntwice()";
+    let (sources, result) = assemble_file("twice.casm", source);
+    let errors = result.unwrap_err();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].span, 13..18);
+    let calls: Vec<&str> = errors[0].expanded_from.iter().map(|call| call.name.as_str()).collect();
+    assert_eq!(calls, ["read", "twice"]);
+    assert_eq!(
+        errors[0].render(&sources),
+        "twice.casm:1:14: \"innum\" needs the decimal extension (add `dialect decimal` first)
+  |
+1 | macro read() innum endmacro
+  |              ^^^^^
+twice.casm:2:15: note: in this expansion of \"read\"
+  |
+2 | macro twice() read() read() endmacro
+  |               ^^^^^^
+twice.casm:3:1: note: in this expansion of \"twice\"
+  |
+3 | twice()
+  | ^^^^^^^
+"
+    );
+    assert_eq!(
+        asm::fmt::format("macro pair(a,b) push a\npush b endmacro pair('a','b')").unwrap(),
+        "macro pair(a, b)\n    push a\n    push b\nendmacro\npair('a', 'b')\n"
+    );
+}
+
+#[test]
+fn includes() {
+    let directory = std::env::temp_dir().join(format!("stackcell-include-{}", std::process::id()));
+    std::fs::create_dir_all(directory.join("lib")).unwrap();
+    let write = |name: &str, source: &str| std::fs::write(directory.join(name), source).unwrap();
+    write("lib/shout.casm", "include \"std/io.casm\" macro shout(text) print_string(text) endmacro");
+    write("a.casm", "include \"b.casm\"");
+    write("b.casm", "include \"a.casm\"");
+    let assemble = |source: &str, search_paths: Vec<std::path::PathBuf>| {
+        let mut sources = Sources::new(search_paths);
+        let file = sources.add(directory.join("main.casm").display().to_string(), source);
+        asm::assemble_file(&mut sources, file).map_err(|errors| errors[0].message.clone())
+    };
+    // files are looked for beside the file including them, then in the search
+    // paths, and each is only read once
+    assert_eq!(
+        assemble("include \"lib/shout.casm\" include \"std/io.casm\" shout(\"hi\")", vec![]),
+        Ok("#00\"ih\":[;:]`".to_string())
+    );
+    assert_eq!(
+        assemble("include \"shout.casm\" shout(\"hi\")", vec![directory.join("lib")]),
+        Ok("#00\"ih\":[;:]`".to_string())
+    );
+    assert_eq!(
+        assemble("include \"shout.casm\"", vec![]),
+        Err("Can't find \"shout.casm\" to include".to_string())
+    );
+    assert_eq!(
+        assemble("include \"a.casm\"", vec![]),
+        Err(format!(
+            "Files include each other: {0}/a.casm -> {0}/b.casm -> {0}/a.casm",
+            directory.display()
+        ))
+    );
+    std::fs::remove_dir_all(directory).unwrap();
+    // the standard library can be included from anywhere
+    assert_eq!(
+        asm::assemble("include \"std/stack.casm\" push 'a' copy_to_secondary()").unwrap(),
+        "'a:{X}X"
+    );
+    assert_eq!(error("macro m() include \"std/io.casm\" endmacro"), "Files can't be included inside macros");
+}
+
+/// Run the assembly `source` with `input`, returning its output.
+fn run(source: &str, input: &[u8]) -> String {
+    let program = asm::assemble(source).unwrap();
+    let mut computer = Computer::with_io(program.as_bytes(), input, vec![]);
+    computer.run().unwrap();
+    String::from_utf8(computer.output).unwrap()
+}
+
+#[test]
+fn library() {
+    let io = "include \"std/io.casm\" include \"std/stack.casm\" ";
+    assert_eq!(run(&format!("{}print_string(\"hi\")", io), b""), "hi");
+    for n in ["#0", "#7", "#42", "#255"] {
+        assert_eq!(
+            run(&format!("{}push {} print_decimal()", io, n), b""),
+            n.trim_start_matches('#')
+        );
+    }
+    assert_eq!(run(&format!("{}push $00 push \"abc\" reverse() print_stack()", io), b""), "cba");
+    // lines are read last character first
+    let source = format!("{}read_line() reverse() print_stack() read_line() print_stack()", io);
+    assert_eq!(run(&source, b"one\ntwo\nthree"), "oneowt");
+    assert_eq!(run(&format!("{}read_line() print_stack()", io), b"end"), "dne");
+    let source = format!("{}push 'a' copy_to_secondary() out swap stacks out", io);
+    assert_eq!(run(&source, b""), "aa");
+    let source = format!("{}push 'a' to_secondary() push 'b' out copy_to_primary() out", io);
+    assert_eq!(run(&source, b""), "ba");
+}
+
+#[test]
+fn strings() {
+    assert_eq!(asm::assemble(r#"push "a\tb\\\n\x41""#).unwrap(), "\"A\n\\b\ta\"");
+    // quotes and bytes which aren't ASCII are pushed on their own
+    assert_eq!(asm::assemble(r#"push "say \"é\"""#).unwrap(), "'\"#a9#c3'\"\" yas\"");
+    assert_eq!(asm::assemble(r#"push "\x80\x22""#).unwrap(), "'\"#80");
+    assert_eq!(asm::assemble(r#"push """#).unwrap(), "");
+    let io = "include \"std/io.casm\" ";
+    let text = "\"quoted\", naïve \\ 日本\t\n";
+    let escaped = text.replace('\\', "\\\\").replace('"', "\\\"").replace('\t', "\\t");
+    // strings are pushed a byte at a time, and each is printed as a character
+    let output = run(&format!("{}print_string(\"{}\")", io, escaped), b"");
+    assert_eq!(output.chars().map(|c| c as u8).collect::<Vec<_>>(), text.as_bytes());
+    assert_eq!(asm::assemble(r#"raw "\"a\"\x3b""#).unwrap(), "\"a\";");
+    assert_eq!(error(r#"push "a\qb""#), "Unknown escape `\\q` in string");
+    assert_eq!(error(r#"push "\x4""#), "Expected two hex digits after `\\x` but found \"4\"");
+    assert_eq!(error(r#"raw "\xff""#), "Raw code can't escape bytes which aren't ASCII");
+    let (sources, result) = assemble_file("s.casm", "push \"ab\\ec\"\n");
+    assert_eq!(
+        result.unwrap_err()[0].render(&sources),
+        "s.casm:1:9: Unknown escape `\\e` in string\n  |\n1 | push \"ab\\ec\"\n  |         ^^\n"
+    );
+}
+
+#[test]
+fn blocks() {
+    assert_eq!(asm::assemble("in while nonzero { out in }").unwrap(), "@[;@]");
+    assert_eq!(asm::assemble("in if { push 'y' out }").unwrap(), "@['y;#00]");
+    assert_eq!(
+        asm::assemble("in if { push 'y' out } else { push 'n' out }").unwrap(),
+        "@#00x[`'y;#01#00]('n;#01)"
+    );
+    assert_eq!(asm::assemble("repeat 3 { out } repeat #0 { in }").unwrap(), ";;;");
+    // labels in the body of a repeat are its own each time
+    let source = "repeat 2 { in jz end push 'x' out end: }";
+    assert_eq!(asm::assemble(source).unwrap(), "@?13'x;@?13'x;");
+    assert_eq!(run(source, b"\0a"), "x");
+    let source = "repeat 2 { repeat 2 { in dup jz end out end: } }";
+    assert_eq!(run(source, b"a\0bc"), "abc");
+    assert_eq!(
+        error("repeat 2 { jmp end push \"far too far\" end: }"),
+        "Label \"end\" is 13 bytes past the jump to it, but a jump can skip at most 9"
+    );
+    // the value tested is consumed, whichever way it goes
+    for (value, output) in [("#0", "n"), ("#1", "y"), ("'a'", "y")] {
+        let source = format!(
+            "push 'e' push {} if {{ push 'y' out }} else {{ push 'n' out }} out",
+            value
+        );
+        assert_eq!(run(&source, b""), format!("{}e", output));
+        let source = format!("push 'e' push {} if {{ push 'y' out }} out", value);
+        assert_eq!(run(&source, b""), output.replace('n', "") + "e");
+    }
+    let source = "in dup while nonzero { dup eq 'b' if { drop repeat 2 { push 'B' out } } \
+                  else { out } in dup }";
+    assert_eq!(run(source, b"abc\0"), "aBBc");
+    assert_eq!(run("push #03 dup while nonzero { push '.' out sub $01 dup }", b""), "...");
+    // brackets in literals are pushed by value, so they aren't taken for loops
+    assert_eq!(asm::assemble("push '[' push \"a(b)\"").unwrap(), "#5b#29\"b\"#28\"a\"");
+    assert_eq!(run("push #0 if { push \"(]\" out out } push 'k' out", b""), "k");
+    assert_eq!(run("push #1 if { push \"(]\" out out } push 'k' out", b""), "(]k");
+    let source = "push #02 dup while nonzero { push ')' out push \"[\" out sub $01 dup }";
+    assert_eq!(run(source, b""), ")[)[");
+    // subroutines in blocks keep their names
+    assert_eq!(
+        asm::assemble("dialect calls call greet in if { def a ret } def greet ret").unwrap(),
+        "#!stackcell 1 calls\nCb@[.DaR#00]DbR"
+    );
+    assert_eq!(error("if { out"), "This block is never closed with `}`");
+    assert_eq!(error("out }"), "Found `}` without a block to close");
+    assert_eq!(error("{ out }"), "Found `{` without `if`, `while` or `repeat` before it");
+    assert_eq!(error("else { out }"), "Found `else` without an `if` before it");
+    assert_eq!(error("if out"), "Expected `{` but found \"out\"");
+    assert_eq!(error("repeat x { out }"), "Expected repeat count but found \"x\"");
+    assert_eq!(asm::assemble("repeat 12 { out } repeat $02 { in }").unwrap(), ";".repeat(12) + "@@");
+    assert_eq!(error("repeat 'a' { out }"), "Expected repeat count but found \"'a'\"");
+    assert_eq!(
+        error("repeat 99999999999 { out }"),
+        "Repeat count 99999999999 is too large (at most 65536)"
+    );
+    assert_eq!(
+        error(&format!("repeat 65536 {{ repeat 65536 {{ push \"{}\" }} }}", "a".repeat(250))),
+        "Repeating this makes the program more than 16777216 bytes long"
+    );
+    // the body of a repeat 0 is checked, but not kept
+    assert_eq!(asm::assemble("repeat 0 { out a: jz a } in").unwrap(), "@");
+    assert_eq!(
+        error("repeat 0 { jz nowhere }"),
+        "Label \"nowhere\" is jumped to but never defined"
+    );
+    assert_eq!(
+        error("repeat 0 { outnum }"),
+        "\"outnum\" needs the decimal extension (add `dialect decimal` first)"
+    );
+    assert_eq!(error("repeat 0 { bogus }"), "Found invalid token \"bogus\"");
+    assert_eq!(
+        error("dialect calls repeat 0 { def f ret } call f"),
+        "Subroutine \"f\" is called but never defined"
+    );
+    assert_eq!(error("jmp 12"), "Expected jump distance or label but found \"12\"");
+    assert_eq!(error("while nonzero { if { } else { out }"), "This block is never closed with `}`");
+    // errors in a repeated body are only reported once
+    assert_eq!(
+        error("repeat 4 { innum }"),
+        "\"innum\" needs the decimal extension (add `dialect decimal` first)"
+    );
+    assert_eq!(
+        error("repeat 2 { end: end: }"),
+        "Label \"end\" is defined twice"
+    );
+    assert_eq!(
+        asm::fmt::format("in if{push 'y' out}else{while zero{in}} repeat 2 { out }").unwrap(),
+        "in\nif {\n    push 'y'\n    out\n} else {\n    while zero {\n        in\n    }\n}\nrepeat \
+         2 {\n    out\n}\n"
+    );
+}
+
+#[test]
+fn source_maps() {
+    let mut sources = Sources::default();
+    let source = "dialect decimal\ninclude \"std/io.casm\"\npush #07\n\
+                  print_decimal()\nin if {\n    outnum\n}\n";
+    let file = sources.add("count.casm", source);
+    let (program, spans) = asm::assemble_files(&mut sources, &[file]).unwrap();
+    let map = asm::source_map(&sources, &spans);
+    let tape = program.strip_prefix("#!stackcell 1 decimal\n").unwrap();
+    // offsets are into the program after its header
+    assert_eq!(tape.find("#07"), Some(0));
+    assert_eq!(map.locate(0).unwrap().to_string(), "count.casm:3:1");
+    assert_eq!(map.locate(2).unwrap().to_string(), "count.casm:3:1");
+    // code expanded from a macro comes from the macro's definition
+    let location = map.locate(3).unwrap();
+    assert_eq!(location.file, "std/io.casm");
+    let outnum = tape.rfind('n').unwrap();
+    assert_eq!(map.locate(outnum).unwrap().to_string(), "count.casm:6:5");
+    // and the code closing a block comes from the block
+    assert_eq!(map.locate(outnum + 1).unwrap().to_string(), "count.casm:5:4");
+    assert_eq!(SourceMap::parse(&map.to_string()).unwrap(), map);
+
+    let mut map = SourceMap::default();
+    map.add(0, "a.casm", 1, 1);
+    map.add(2, "a.casm", 1, 1);
+    map.add(2, "b.casm", 4, 2);
+    map.add(5, "a.casm", 2, 1);
+    assert_eq!(
+        map.to_string(),
+        "stackcell-map 1\nfile a.casm\nfile b.casm\n0 0 1 1\n2 1 4 2\n5 0 2 1\n"
+    );
+    assert_eq!(map.locate(4).unwrap().to_string(), "b.casm:4:2");
+    assert_eq!(SourceMap::default().locate(0), None);
+    assert_eq!(
+        SourceMap::parse("stackcell-map 1\nfile a.casm\n0 1 1 1\n").unwrap_err(),
+        "Line 3 of the source map uses file 1, which isn't given"
+    );
+    assert_eq!(
+        SourceMap::parse("stackcell-map 1\nfile a.casm\n2 0 1 1\n1 0 1 1\n").unwrap_err(),
+        "Line 4 of the source map is out of order"
+    );
+    assert_eq!(
+        SourceMap::parse("stackcell-map 1\n0 0 1\n").unwrap_err(),
+        "Line 2 of the source map isn't a mapping: \"0 0 1\""
+    );
+}
+
+#[test]
+fn multiple_files() {
+    let mut sources = Sources::default();
+    let first = sources.add("shout.casm", "macro shout(c)\n    push c out\nendmacro\n");
+    let second = sources.add("hi.casm", "push \"hi\" out out\nshout('!')\nif { }\n");
+    // macros from one file can be used in the others
+    let (program, spans) = asm::assemble_files(&mut sources, &[first, second]).unwrap();
+    assert_eq!(program, "\"ih\";;'!;[#00]");
+    assert_eq!(
+        asm::listing(&sources, &program, &spans),
+        "    0  \"ih\";;        hi.casm:1  push \"hi\" out out\n    \
+         6  '!;           shout.casm:2      push c out\n    \
+         9  [#00]         hi.casm:3  if { }\n"
+    );
+    // whichever file they're defined in
+    let mut sources = Sources::default();
+    let first = sources.add("hi.casm", "shout('!')\n");
+    let second = sources.add("shout.casm", "macro shout(c)\n    push c out\nendmacro\n");
+    assert_eq!(asm::assemble_files(&mut sources, &[first, second]).unwrap().0, "'!;");
+}
+
+/// Each `.casm` file in tests/corpus is split into the tokens listed in the
+/// `.tokens` file beside it, and assembles into the `.cel` file.
+#[test]
+fn corpus() {
+    for entry in std::fs::read_dir("tests/corpus").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension() != Some("casm".as_ref()) {
+            continue;
+        }
+        let source = std::fs::read_to_string(&path).unwrap();
+        let tokens = asm::tokens(&source).join("\n") + "\n";
+        assert_eq!(
+            tokens,
+            std::fs::read_to_string(path.with_extension("tokens")).unwrap(),
+            "{} is split into different tokens",
+            path.display()
+        );
+        assert_eq!(
+            asm::assemble(&source).unwrap(),
+            std::fs::read_to_string(path.with_extension("cel")).unwrap(),
+            "{} assembles differently",
+            path.display()
+        );
+    }
+}
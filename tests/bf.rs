@@ -0,0 +1,59 @@
+mod common;
+
+use stackcell::bf;
+
+/// Check that the translation of `source` behaves like the BF reference, and
+/// return the output.
+fn check(source: &str, input: &[u8]) -> Vec<u8> {
+    let program = bf::parse(source).unwrap();
+    let expected = bf::interpret(&program, input);
+    let actual = common::interpret(bf::to_stackcell(&program).as_bytes(), input);
+    assert_eq!(actual, common::printed(&expected), "output of {:?} differs", source);
+    expected
+}
+
+#[test]
+fn programs() {
+    assert_eq!(
+        check(
+            "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.\
+             <-.<.+++.------.--------.>>+.>++.",
+            b""
+        ),
+        b"Hello World!\n"
+    );
+    assert_eq!(check(",[.,]", b"cat"), b"cat");
+    assert_eq!(check(">,[>,]<[.<]", b"reversed"), b"desrever");
+    // moving left of the starting cell
+    assert_eq!(check("<<+++++[>++++++++++<-]>.<<<.>>>>>.", b""), b"2\0\0");
+    // wrapping, clear loops, and runs which cancel out
+    assert_eq!(check("-.[-]+.+-+-[+].", b""), b"\xFF\x01\0");
+    check(&"+".repeat(300), b"");
+    check(&format!("{}.", "-".repeat(300)), b"");
+}
+
+#[test]
+fn folding() {
+    let translate = |source| bf::to_stackcell(&bf::parse(source).unwrap());
+    assert_eq!(translate("+++"), "#03+");
+    assert_eq!(translate("---"), "#03x-");
+    assert_eq!(translate("++-+"), "#02+");
+    assert_eq!(translate(">><"), "X{X}");
+    assert_eq!(translate("[-]"), "`#00");
+    assert_eq!(translate("[+]"), "`#00");
+    assert_eq!(translate("[+-]"), ":[:]");
+    assert_eq!(translate("[--]"), ":[#02x-:]");
+    assert_eq!(translate("comments, and.\ninstructions"), "`@:;");
+}
+
+#[test]
+fn unmatched_brackets() {
+    assert_eq!(
+        bf::parse("+[\n>[-]").unwrap_err(),
+        "Unmatched '[' at line 1, column 2"
+    );
+    assert_eq!(
+        bf::parse("+[]\n  ]").unwrap_err(),
+        "Unmatched ']' at line 2, column 3"
+    );
+}
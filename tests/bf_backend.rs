@@ -0,0 +1,76 @@
+// the compiled BF does arithmetic with decrement loops, which take billions of
+// steps with 32-bit cells, so these only run with 8-bit ones
+#![cfg(not(feature = "u32"))]
+
+mod common;
+
+use stackcell::program::Program;
+use stackcell::{backend, bf};
+
+fn execute(tape: &[u8], input: &[u8]) -> Vec<u8> {
+    let code = backend::bf::compile(&Program::new(tape));
+    bf::interpret(&bf::parse(&code).unwrap(), input)
+}
+
+fn check(tape: &[u8], input: &[u8]) {
+    assert_eq!(
+        execute(tape, input),
+        common::interpret(tape, input),
+        "output of {:?} differs",
+        String::from_utf8_lossy(tape)
+    );
+}
+
+#[test]
+fn samples() {
+    for (name, input) in &[
+        ("cat", &b"Hello, cat!\n"[..]),
+        ("error_quine", b""),
+        ("hello", b""),
+        ("quine", b""),
+        ("quine_family", b""),
+        ("tac", b"reversed\n"),
+        ("tac_line", b"one line\nanother\n"),
+        ("truth_machine", b"0"),
+    ] {
+        let tape = std::fs::read(format!("samples/{}.cel", name)).unwrap();
+        check(&tape, input);
+    }
+}
+
+#[test]
+fn edge_cases() {
+    check(b"1\"'A;.", b"");
+    check(b"'A;9", b"");
+    check(b"#FF#02+;#00#01-;#10#10*;~;", b"");
+    check(b"#01[\"!ko\":[;:]", b"");
+    check(b"[", b"");
+    check(b"#01]", b"");
+    check(b"'a;Q", b"");
+    check(b"'a;#0G", b"");
+    check(b"@@@+:!?;'0+;", b"\x01");
+}
+
+#[test]
+fn arithmetic() {
+    check(b"#03#11/;#03#11%;#07#07/;#09#02%;", b"");
+    check(b"#03#05<;#05#03<;#04#04<;#03#05>;#05#03>;#04#04>;", b"");
+    check(b"#03#05=;#04#04=;#00!;#07!;", b"");
+    check(b"#0C#0A^;#0C#0A&;#0C#0A|;#FF#A5^;#00#FF&;", b"");
+    check(b"#05xX;X;{;{;}'z;", b"");
+    check(b"#2A`'x;;;", b"");
+}
+
+#[test]
+fn differences() {
+    // values from 128 are written as UTF-8, as the interpreter writes them
+    check(b"#7F;#80;#BF;#C0;#E9;#FF;", b"");
+    assert_eq!(execute(b"#E9;", b""), "\u{e9}".as_bytes());
+    // dividing by zero halts, where the interpreter also reports it on stderr
+    check(b"'a;#00#01/;'b;", b"");
+    check(b"'a;#00#01%;'b;", b"");
+    assert_eq!(execute(b"'a;#00#01/;'b;", b""), b"a");
+    // other errors are printed, as the interpreter prints them with
+    // --classic-errors
+    assert_eq!(execute(b"'a;#01]", b""), b"aError: Indexed out of bounds!\n");
+}
@@ -0,0 +1,110 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+use stackcell::backend;
+use stackcell::program::Program;
+
+fn run(command: &mut Command, input: &[u8]) -> Output {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// Compile `tape` to C, build it with the system `cc` and check its output,
+/// errors and exit status against the interpreter's for the same input,
+/// returning them. Without a C compiler this fails, unless
+/// `STACKCELL_SKIP_C_TESTS` is set, in which case it returns `None`.
+fn check(name: &str, tape: &[u8], input: &[u8]) -> Option<Output> {
+    let dir = std::env::temp_dir().join(format!("stackcell-c-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let source: PathBuf = dir.join(format!("{}.c", name));
+    let binary: PathBuf = dir.join(name);
+    let cel: PathBuf = dir.join(format!("{}.cel", name));
+    std::fs::write(&source, backend::c::compile(&Program::new(tape))).unwrap();
+    std::fs::write(&cel, tape).unwrap();
+    let status = match Command::new("cc").arg("-o").arg(&binary).arg(&source).status() {
+        Ok(status) => status,
+        Err(_) if std::env::var_os("STACKCELL_SKIP_C_TESTS").is_some() => {
+            eprintln!("skipping {}: no C compiler", name);
+            return None;
+        }
+        Err(err) => panic!(
+            "couldn't run cc for {} ({}); set STACKCELL_SKIP_C_TESTS=1 to skip the C backend tests",
+            name, err
+        ),
+    };
+    assert!(status.success(), "cc failed for {}", name);
+    let expected = run(Command::new(env!("CARGO_BIN_EXE_stackcell")).arg("run").arg(&cel), input);
+    let actual = run(&mut Command::new(&binary), input);
+    assert_eq!(
+        String::from_utf8_lossy(&actual.stdout),
+        String::from_utf8_lossy(&expected.stdout),
+        "output of {} differs",
+        name
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&actual.stderr),
+        String::from_utf8_lossy(&expected.stderr),
+        "errors of {} differ",
+        name
+    );
+    assert_eq!(actual.status.code(), expected.status.code(), "exit status of {} differs", name);
+    Some(actual)
+}
+
+#[test]
+fn samples() {
+    for (name, input) in &[
+        ("cat", &b"Hello, cat!\n"[..]),
+        ("error_quine", b""),
+        ("hello", b""),
+        ("quine", b""),
+        ("quine_family", b""),
+        ("tac", b"reversed\n"),
+        ("tac_line", b"one line\nanother\n"),
+        ("truth_machine", b"0"),
+    ] {
+        let tape = std::fs::read(format!("samples/{}.cel", name)).unwrap();
+        check(name, &tape, input);
+    }
+}
+
+#[test]
+fn edge_cases() {
+    // skipping into a string literal and out of the end of the tape
+    check("skip_into_string", b"3\"'A;\"", b"");
+    check("skip_past_end", b"'A;9", b"");
+    // wrapping arithmetic and non-ASCII output
+    check("wrapping", b"#FF#02+;#00#01-;#10#10*;~;", b"");
+    // unmatched brackets only fail when the jump is taken
+    check("unmatched_untaken", b"#01[\"!ko\":[;:]", b"");
+    check("unmatched_taken", b"[", b"");
+    check("unmatched_close", b"#01]", b"");
+    // errors are reported on stderr after any earlier output
+    check("unknown", b"'a;Q", b"");
+    check("bad_hex", b"'a;#0G", b"");
+    check("divide_by_zero", b"'a;#00#01/'b;", b"");
+    // reading past EOF pushes zero
+    check("eof", b"@@@+:!?;'0+;", b"\x01");
+}
+
+#[test]
+fn errors() {
+    // as the interpreter reports them by default
+    if let Some(output) = check("errors_unknown", b"'a;Q", b"") {
+        assert_eq!(output.stdout, b"a");
+        assert_eq!(output.stderr, b"Error: Unknown instruction: 'Q'\n");
+        assert_eq!(output.status.code(), Some(65));
+    }
+    if let Some(output) = check("errors_divide", b"'a;#00#01/'b;", b"") {
+        assert_eq!(output.stdout, b"a");
+        assert_eq!(output.stderr, b"Error: attempt to divide by zero\n");
+        assert_eq!(output.status.code(), Some(70));
+    }
+}
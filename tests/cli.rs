@@ -0,0 +1,470 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+use stackcell::types::CellSize;
+
+fn stackcell(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_stackcell"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn run() {
+    let output = stackcell(&["run", "samples/hello.cel"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"Hello, world!\n");
+    // a file on its own is run too
+    assert_eq!(stackcell(&["samples/hello.cel"]).stdout, b"Hello, world!\n");
+    for flush in ["byte", "line", "input"] {
+        let output = stackcell(&["run", "--flush", flush, "samples/hello.cel"]);
+        assert_eq!(output.stdout, b"Hello, world!\n");
+    }
+}
+
+#[test]
+fn failures() {
+    let output = stackcell(&["run", "samples/missing.cel"]);
+    assert_eq!(output.status.code(), Some(74));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("Error: samples/missing.cel: "));
+    assert_eq!(stackcell(&["compile", "--to", "cobol", "samples/hello.cel"]).status.code(), Some(2));
+    assert_eq!(stackcell(&["frobnicate", "samples/hello.cel"]).status.code(), Some(2));
+}
+
+#[test]
+fn exit_status() {
+    let output = stackcell(&["run", "samples/error_quine.cel"]);
+    assert_eq!(output.status.code(), Some(65));
+    assert!(output.stdout.is_empty());
+    assert_eq!(output.stderr, b"Error: Unknown instruction: 'E'\n");
+    // the error quine still works the old way
+    for args in [
+        &["run", "--classic-errors", "samples/error_quine.cel"][..],
+        &["--classic-errors", "samples/error_quine.cel"],
+    ] {
+        let output = stackcell(args);
+        assert!(output.status.success());
+        assert_eq!(output.stdout, std::fs::read("samples/error_quine.cel").unwrap());
+    }
+
+    let path = std::env::temp_dir().join(format!("stackcell-cli-{}.cel", std::process::id()));
+    std::fs::write(&path, "'a;#00#01/").unwrap();
+    let output = stackcell(&["run", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(output.stdout, b"a");
+    assert_eq!(output.stderr, b"Error: attempt to divide by zero\n");
+    let output = stackcell(&["run", "--classic-errors", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(101));
+    assert_eq!(output.stderr, b"attempt to divide by zero\n");
+
+    std::fs::write(&path, "#01#2A").unwrap();
+    assert_eq!(stackcell(&["run", path.to_str().unwrap()]).status.code(), Some(0));
+    let output = stackcell(&["run", "--exit-from-stack", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(42));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn eof() {
+    let path = std::env::temp_dir().join(format!("stackcell-eof-{}.cel", std::process::id()));
+    std::fs::write(&path, "'z@@;;;;").unwrap();
+    for (eof, expected) in [
+        ("zero", b"\0az\0"),
+        ("all-ones", b"\xffaz\0"),
+        ("unchanged", b"az\0\0"),
+        ("flag", b"\0\0\x01a"),
+    ] {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_stackcell"))
+            .args(["run", "--eof", eof, path.to_str().unwrap()])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(b"a").unwrap();
+        let output = child.wait_with_output().unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert_eq!(stdout.chars().map(|c| c as u8).collect::<Vec<_>>(), expected, "{}", eof);
+    }
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn encoding() {
+    let path = std::env::temp_dir().join(format!("stackcell-cat-{}.cel", std::process::id()));
+    std::fs::write(&path, "@:[;@:]").unwrap();
+    let cat = |encoding, input: &[u8]| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_stackcell"))
+            .args(["run", "--encoding", encoding, path.to_str().unwrap()])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(input).unwrap();
+        String::from_utf8(child.wait_with_output().unwrap().stdout).unwrap()
+    };
+    // bytes are written back as the characters with those values
+    assert_eq!(cat("bytes", "é".as_bytes()), "\u{c3}\u{a9}");
+    if cfg!(feature = "u32") {
+        assert_eq!(cat("utf8", "héllo→😀".as_bytes()), "héllo→😀");
+        assert_eq!(cat("utf8", b"a\xffb\xe2\x86"), "a\u{fffd}b\u{fffd}");
+        // a byte which can't continue a character starts the next one
+        assert_eq!(cat("utf8", b"x\xc3Ay"), "x\u{fffd}Ay");
+        assert_eq!(cat("utf8", b"\xe2\x86\xe2\x86\x92"), "\u{fffd}→");
+        assert_eq!(cat("utf8", b"\xed\xa0\x80!"), "\u{fffd}\u{fffd}\u{fffd}!");
+    } else {
+        // characters wouldn't fit in a cell
+        let output = stackcell(&["run", "--encoding", "utf8", path.to_str().unwrap()]);
+        assert_eq!(output.status.code(), Some(2));
+        assert!(String::from_utf8(output.stderr).unwrap().contains("needs 32-bit cells"));
+    }
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn decimal() {
+    let path = std::env::temp_dir().join(format!("stackcell-sum-{}.cel", std::process::id()));
+    std::fs::write(&path, "NN+n'\n;NnNn").unwrap();
+    let sum = |args: &[&str]| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_stackcell"))
+            .arg("run")
+            .args(args)
+            .arg(&path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        // programs which fail to parse exit without reading their input
+        let _ = child.stdin.take().unwrap().write_all(b"  12\n-3 x");
+        child.wait_with_output().unwrap()
+    };
+    let output = sum(&["-x", "decimal"]);
+    assert!(output.status.success());
+    // -3 wraps around, a missing number reads as 0, and so does EOF
+    assert_eq!(output.stdout, b"9\n00");
+    // N is an unknown instruction otherwise
+    assert_eq!(sum(&[]).status.code(), Some(65));
+    let output = sum(&["--extension", "decimal", "--eof", "all-ones"]);
+    assert_eq!(output.stdout, format!("9\n0{}", CellSize::MAX).as_bytes());
+    // or the program can ask for the extension itself
+    std::fs::write(&path, "#!stackcell 1 decimal\nNN+n'\n;NnNn").unwrap();
+    assert_eq!(sum(&[]).stdout, b"9\n00");
+    std::fs::write(&path, "#!stackcell 2 decimal\nNN+n").unwrap();
+    let output = sum(&[]);
+    assert_eq!(output.status.code(), Some(65));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn calls() {
+    let path = std::env::temp_dir().join(format!("stackcell-calls-{}.cel", std::process::id()));
+    std::fs::write(&path, "#!stackcell 1 calls\n'aCp'bCp.Dp;#0a;R").unwrap();
+    let path = path.to_str().unwrap();
+    let output = stackcell(&["run", path]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"a\nb\n");
+
+    // unbounded recursion is stopped
+    std::fs::write(path, "#!stackcell 1 calls\nDrCr").unwrap();
+    let output = stackcell(&["run", "--max-call-depth", "100", path]);
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(output.stderr, b"Error: Subroutines called more than 100 deep\n");
+
+    std::fs::write(path, "'aR").unwrap();
+    let output = stackcell(&["run", "-x", "calls", path]);
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(output.stderr, b"Error: Returned without a call\n");
+
+    std::fs::write(path, "#!stackcell 1 calls\nCq").unwrap();
+    let output = stackcell(&["check", path]);
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!("{}: Undefined subroutine 'q' (at offset 0)\nError: Found 1 error\n", path)
+    );
+
+    // a D in a literal doesn't define anything
+    std::fs::write(path, "#!stackcell 1 calls\n\"Dq\"``Cq'k;.Dq'y;R").unwrap();
+    let output = stackcell(&["run", path]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"yk");
+    std::fs::write(path, "#!stackcell 1 calls\n\"Dq\"``Cq.").unwrap();
+    let output = stackcell(&["check", path]);
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!("{}: Undefined subroutine 'q' (at offset 6)\nError: Found 1 error\n", path)
+    );
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn heap() {
+    let path = std::env::temp_dir().join(format!("stackcell-heap-{}.cel", std::process::id()));
+    std::fs::write(&path, "#!stackcell 1 heap\n'h#03p'i#04p#03g;#04g;#09g;").unwrap();
+    let path = path.to_str().unwrap();
+    let output = stackcell(&["run", path]);
+    assert!(output.status.success());
+    // cells which haven't been stored to read as 0
+    assert_eq!(output.stdout, b"hi\0");
+
+    let output = stackcell(&["run", "--heap-limit", "4", path]);
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(output.stdout, b"");
+    assert_eq!(
+        output.stderr,
+        b"Error: Heap address 4 is out of bounds (the heap holds 4 cells)\n"
+    );
+    // 3 and 4 wrap around to 1 and 0, and 9 to 1
+    let output = stackcell(&["run", "--heap-limit", "2", "--heap-bounds", "wrap", path]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"hih");
+
+    // traces show the cell last used
+    std::fs::write(path, "#!stackcell 1 heap\n'h#03p#03g").unwrap();
+    let output = stackcell(&["run", "--trace", path]);
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "0 '\\'' | primary: (empty) | secondary: (empty) | cell: 0\n\
+         2 '#' | primary: 104 | secondary: (empty) | cell: 0\n\
+         5 'p' | primary: 104 3 | secondary: (empty) | cell: 0\n\
+         6 '#' | primary: (empty) | secondary: (empty) | cell: 0 | heap[3]: 104\n\
+         9 'g' | primary: 3 | secondary: (empty) | cell: 0 | heap[3]: 104\n"
+    );
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn check() {
+    let output = stackcell(&["check", "samples/hello.cel", "samples/tac.cel"]);
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+    // error_quine's errors are deliberate
+    let output = stackcell(&["check", "samples/error_quine.cel"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "samples/error_quine.cel: Unknown instruction: 'E' (at offset 0)\nError: Found 1 \
+         error\n"
+    );
+
+    // extensions have to be enabled to be checked
+    let path = std::env::temp_dir().join(format!("stackcell-check-{}.cel", std::process::id()));
+    std::fs::write(&path, "Nn").unwrap();
+    let path = path.to_str().unwrap();
+    assert_eq!(stackcell(&["check", path]).status.code(), Some(1));
+    assert!(stackcell(&["check", "-x", "decimal", path]).status.success());
+    // but can't be compiled
+    std::fs::write(path, "#!stackcell 1 decimal\nNn").unwrap();
+    assert!(stackcell(&["check", path]).status.success());
+    let output = stackcell(&["compile", "--to", "c", path]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stderr, b"Error: The decimal extension can't be compiled\n");
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn disasm() {
+    let output = stackcell(&["disasm", "samples/cat.cel"]);
+    assert!(output.status.success());
+    assert_eq!(
+        output.stdout,
+        b"in\ndup\nwhile nonzero\n    out\n    in\n    dup\nnext nonzero\n"
+    );
+}
+
+#[test]
+fn asm() {
+    let path = std::env::temp_dir().join(format!("stackcell-asm-{}.casm", std::process::id()));
+    std::fs::write(&path, "push #12 # twelve\nout ; print it\n#1st comment\n").unwrap();
+    let output = stackcell(&["asm", path.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"#0c;");
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!(
+            "Warning: {}: comments starting with `#` are deprecated (line 1 and 1 other); \
+             start them with `;` instead, or convert them with `stackcell fmt --write`\n",
+            path.display()
+        )
+    );
+
+    // every error is reported, with where it is
+    std::fs::write(&path, "push #300\nswap foo\n").unwrap();
+    let output = stackcell(&["asm", path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!(
+            "{0}:1:6: Decimal literal #300 is out of range (at most 255)\n  |\n1 | push #300\n  |      \
+             ^^^^\n{0}:2:6: Expected swap type but found \"foo\"\n  |\n2 | swap foo\n  |      \
+             ^^^\nError: Found 2 errors\n",
+            path.display()
+        )
+    );
+    std::fs::remove_file(path).unwrap();
+
+    // included files are looked for in the directories given
+    let directory = std::env::temp_dir().join(format!("stackcell-asm-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    std::fs::write(directory.join("bang.casm"), "macro bang() push '!' out endmacro\nbang(1)\n")
+        .unwrap();
+    let path = std::env::temp_dir().join(format!("stackcell-asm-{}-main.casm", std::process::id()));
+    std::fs::write(&path, "include \"bang.casm\"\n").unwrap();
+    let output = stackcell(&["asm", path.to_str().unwrap(), "-I", directory.to_str().unwrap()]);
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!(
+            "{}:2:1: Macro \"bang\" takes 0 arguments but was given 1\n  |\n2 | bang(1)\n  | \
+             ^^^^^^^\nError: Found 1 error\n",
+            directory.join("bang.casm").display()
+        )
+    );
+    std::fs::remove_file(path).unwrap();
+    std::fs::remove_dir_all(directory).unwrap();
+
+    // several files are assembled into one program, written with -o
+    let prefix = std::env::temp_dir().join(format!("stackcell-asm-{}", std::process::id()));
+    let path = |name: &str| format!("{}-{}", prefix.display(), name);
+    std::fs::write(path("a.casm"), "macro twice(c) push c out push c out endmacro\n").unwrap();
+    std::fs::write(path("b.casm"), "twice('z')\n").unwrap();
+    let output = stackcell(&["asm", &path("a.casm"), &path("b.casm"), "-o", &path("out.cel")]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(std::fs::read(path("out.cel")).unwrap(), b"'z;'z;");
+    let output = stackcell(&["run", &path("out.cel")]);
+    assert_eq!(output.stdout, b"zz");
+    // and a listing is printed instead of the program
+    let output = stackcell(&["asm", &path("a.casm"), &path("b.casm"), "--listing"]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!(
+            "    0  'z;'z;        {}:1  macro twice(c) push c out push c out endmacro\n",
+            path("a.casm")
+        )
+    );
+    for name in ["a.casm", "b.casm", "out.cel"] {
+        std::fs::remove_file(path(name)).unwrap();
+    }
+}
+
+#[test]
+fn source_map() {
+    let prefix = std::env::temp_dir().join(format!("stackcell-map-{}", std::process::id()));
+    let path = |extension: &str| prefix.with_extension(extension).to_str().unwrap().to_string();
+    std::fs::write(path("casm"), "push 'a' out\npush #05\ndiv #00\n").unwrap();
+    let output = stackcell(&["asm", &path("casm"), "--source-map", &path("map")]);
+    assert!(output.status.success());
+    std::fs::write(path("cel"), output.stdout).unwrap();
+    let output = stackcell(&["run", &path("cel"), "--source-map", &path("map")]);
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!("Error: attempt to divide by zero (at {}:3:1)\n", path("casm"))
+    );
+    // and traces say where each instruction came from
+    let output = stackcell(&["run", &path("cel"), "--source-map", &path("map"), "--trace"]);
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!(
+            "0 '\\'' (at {casm}:1:1) | primary: (empty) | secondary: (empty) | cell: 0\n\
+             2 ';' (at {casm}:1:10) | primary: 97 | secondary: (empty) | cell: 0\n\
+             3 '#' (at {casm}:2:1) | primary: (empty) | secondary: (empty) | cell: 0\n\
+             6 '#' (at {casm}:3:1) | primary: 5 | secondary: (empty) | cell: 0\n\
+             9 'x' (at {casm}:3:1) | primary: 5 0 | secondary: (empty) | cell: 0\n\
+             10 '/' (at {casm}:3:1) | primary: 0 5 | secondary: (empty) | cell: 0\n\
+             Error: attempt to divide by zero (at {casm}:3:1)\n",
+            casm = path("casm")
+        )
+    );
+    let output = stackcell(&["run", &path("cel"), "--source-map", &path("casm")]);
+    assert_eq!(output.status.code(), Some(74));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!("Error: {}: A source map must start with \"stackcell-map 1\"\n", path("casm"))
+    );
+    for extension in ["casm", "map", "cel"] {
+        std::fs::remove_file(path(extension)).unwrap();
+    }
+}
+
+#[test]
+fn repl() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_stackcell"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"'a'b\nx;\n:input \"h\\x69\"\n@@;;\n:undo\n:undo\n#05{\n:cell\n:reset\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Enter a program to run it, or :help for help
+primary: 97 98 | secondary: (empty) | cell: 0
+a
+primary: 98 | secondary: (empty) | cell: 0
+ih
+primary: 98 | secondary: (empty) | cell: 0
+primary: 98 | secondary: (empty) | cell: 0
+primary: 98 | secondary: (empty) | cell: 0
+primary: 98 | secondary: (empty) | cell: 5
+cell: 5
+primary: (empty) | secondary: (empty) | cell: 0
+"
+    );
+
+    // undoing a :load also takes away the extensions it enabled
+    let path = std::env::temp_dir().join(format!("stackcell-repl-{}.cel", std::process::id()));
+    std::fs::write(&path, "#!stackcell 1 decimal\n#2an").unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_stackcell"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let input = format!(":load {}\n#07n\n:undo\n:undo\n#07n\n", path.display());
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Enter a program to run it, or :help for help
+42
+primary: (empty) | secondary: (empty) | cell: 0
+7
+primary: (empty) | secondary: (empty) | cell: 0
+primary: (empty) | secondary: (empty) | cell: 0
+primary: (empty) | secondary: (empty) | cell: 0
+Error: Unknown instruction: 'n'
+primary: 7 | secondary: (empty) | cell: 0
+"
+    );
+    std::fs::remove_file(path).unwrap();
+
+    // a line starting with `:` is a program unless a command's name follows it
+    let mut child = Command::new(env!("CARGO_BIN_EXE_stackcell"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"#00'i'h\n:[;:]\n`'a\n:'bx;;;\n:cell\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "Enter a program to run it, or :help for help
+primary: 0 105 104 | secondary: (empty) | cell: 0
+hi
+primary: 0 | secondary: (empty) | cell: 0
+primary: 97 | secondary: (empty) | cell: 0
+aba
+primary: (empty) | secondary: (empty) | cell: 0
+cell: 0
+"
+    );
+}
@@ -0,0 +1,36 @@
+//! Helpers for the tests which check programs against the interpreter.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Run `tape` in the interpreter with `input`, returning what it prints.
+/// Errors are printed to stdout, as the classic interpreter (and compiled
+/// programs) print them.
+pub fn interpret(tape: &[u8], input: &[u8]) -> Vec<u8> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "stackcell-test-{}-{}.cel",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&path, tape).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_stackcell"))
+        .args(["run", "--classic-errors"])
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    let output = child.wait_with_output().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    output.stdout
+}
+
+/// What the interpreter prints for the values `bytes`: each as a character,
+/// UTF-8 encoded (so 128 and above take two bytes).
+#[allow(dead_code)] // the BF backend's output is already encoded
+pub fn printed(bytes: &[u8]) -> Vec<u8> {
+    bytes.iter().map(|&byte| byte as char).collect::<String>().into_bytes()
+}
@@ -0,0 +1,45 @@
+use stackcell::dialect::{Dialect, Extension};
+use stackcell::program::Program;
+
+#[test]
+fn headers() {
+    assert_eq!(Dialect::parse(b"'a;").unwrap(), (Dialect::default(), &b"'a;"[..]));
+    let decimal = Dialect::new([Extension::Decimal]);
+    assert_eq!(
+        Dialect::parse(b"#!stackcell 1 decimal decimal\n'a;").unwrap(),
+        (decimal.clone(), &b"'a;"[..])
+    );
+    assert_eq!(Dialect::parse(b"#!stackcell 1").unwrap(), (Dialect::default(), &b""[..]));
+    assert_eq!(decimal.header(), "#!stackcell 1 decimal\n");
+    assert_eq!(Dialect::default().header(), "");
+    assert_eq!(
+        Dialect::parse(b"#!stackcell 2\n").unwrap_err(),
+        "Unsupported version \"2\" in header (expected 1)"
+    );
+    assert_eq!(
+        Dialect::parse(b"#!stackcell\n").unwrap_err(),
+        "Missing version in header (expected 1)"
+    );
+    assert_eq!(
+        Dialect::parse(b"#!stackcell 1 decimals\n").unwrap_err(),
+        "Unknown extension \"decimals\""
+    );
+}
+
+#[test]
+fn extensions_only_use_unknown_instructions() {
+    let mut seen = vec![];
+    for &extension in Extension::ALL {
+        assert_eq!(extension.name().parse(), Ok(extension));
+        for &byte in extension.instructions() {
+            assert!(Program::new(&[byte]).instructions[0].is_err(), "{:?}", byte as char);
+            assert!(!seen.contains(&byte), "{:?} is in two extensions", byte as char);
+            seen.push(byte);
+            // which the extension recognises, even without an operand
+            let dialect = Dialect::new([extension]);
+            if let Err(e) = &Program::with_dialect(&[byte], dialect).instructions[0] {
+                assert!(!e.starts_with("Unknown instruction"), "{:?}: {}", byte as char, e);
+            }
+        }
+    }
+}
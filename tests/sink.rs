@@ -0,0 +1,46 @@
+use std::cell::RefCell;
+use std::io::{Error, Write};
+use std::rc::Rc;
+
+use stackcell::sink::{Policy, Sink};
+use stackcell::types::Computer;
+
+/// Records each write it's given separately.
+#[derive(Clone, Default)]
+struct Writes(Rc<RefCell<Vec<Vec<u8>>>>);
+
+impl Write for Writes {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        self.0.borrow_mut().push(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Run `program` with `input`, returning the writes its output was split into.
+fn writes(program: &[u8], input: &[u8], policy: Policy) -> Vec<Vec<u8>> {
+    let writes = Writes::default();
+    let mut computer = Computer::with_io(program, input, Sink::new(writes.clone(), policy));
+    computer.run().unwrap();
+    writes.0.take()
+}
+
+#[test]
+fn policies() {
+    let program = b"\"\nc\nba\";;;;;@`'d;.'e;";
+    assert_eq!(writes(program, b"", Policy::Byte), [&b"a"[..], b"b", b"\n", b"c", b"\n", b"d"]);
+    assert_eq!(writes(program, b"", Policy::Line), [&b"ab\n"[..], b"c\n", b"d"]);
+    assert_eq!(writes(program, b"", Policy::Input), [&b"ab\nc\n"[..], b"d"]);
+}
+
+#[test]
+fn flushed_on_error() {
+    let writes = Writes::default();
+    let output = Sink::new(writes.clone(), Policy::Input);
+    let mut computer = Computer::with_io(b"'a;'b;E", &b""[..], output);
+    assert!(computer.run().is_err());
+    assert_eq!(*writes.0.borrow(), [b"ab"]);
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use stackcell::backend;
+use stackcell::program::Program;
+use wasmi::{Caller, Engine, Linker, Module, Store};
+
+struct Io {
+    input: Vec<u8>,
+    position: usize,
+    output: Vec<u8>,
+}
+
+fn execute(tape: &[u8], input: &[u8]) -> Vec<u8> {
+    let wasm = backend::wasm::assemble(&Program::new(tape)).unwrap();
+    let engine = Engine::default();
+    let module = Module::new(&engine, &wasm[..]).unwrap();
+    let mut store = Store::new(
+        &engine,
+        Io {
+            input: input.to_vec(),
+            position: 0,
+            output: vec![],
+        },
+    );
+    let mut linker = <Linker<Io>>::new(&engine);
+    linker
+        .func_wrap("env", "read_byte", |mut caller: Caller<'_, Io>| -> i32 {
+            let io = caller.data_mut();
+            match io.input.get(io.position) {
+                Some(&byte) => {
+                    io.position += 1;
+                    byte as i32
+                },
+                None => -1,
+            }
+        })
+        .unwrap();
+    linker
+        .func_wrap("env", "write_byte", |mut caller: Caller<'_, Io>, byte: i32| {
+            caller.data_mut().output.push(byte as u8);
+        })
+        .unwrap();
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .unwrap()
+        .start(&mut store)
+        .unwrap();
+    let run = instance.get_typed_func::<(), ()>(&store, "run").unwrap();
+    run.call(&mut store, ()).unwrap();
+    store.into_data().output
+}
+
+fn check(tape: &[u8], input: &[u8]) {
+    assert_eq!(
+        common::printed(&execute(tape, input)),
+        common::interpret(tape, input),
+        "output of {:?} differs",
+        String::from_utf8_lossy(tape)
+    );
+}
+
+#[test]
+fn samples() {
+    for (name, input) in &[
+        ("cat", &b"Hello, cat!\n"[..]),
+        ("error_quine", b""),
+        ("hello", b""),
+        ("quine", b""),
+        ("quine_family", b""),
+        ("tac", b"reversed\n"),
+        ("tac_line", b"one line\nanother\n"),
+        ("truth_machine", b"0"),
+    ] {
+        let tape = std::fs::read(format!("samples/{}.cel", name)).unwrap();
+        check(&tape, input);
+    }
+}
+
+#[test]
+fn edge_cases() {
+    check(b"1\"'A;.", b"");
+    check(b"'A;9", b"");
+    check(b"#FF#02+;#00#01-;#10#10*;~;", b"");
+    check(b"#01[\"!ko\":[;:]", b"");
+    check(b"[", b"");
+    check(b"#01]", b"");
+    check(b"'a;Q", b"");
+    check(b"'a;#0G", b"");
+    check(b"@@@+:!?;'0+;", b"\x01");
+}
+
+#[test]
+fn deep_stacks() {
+    // push enough to need more than one page of memory on each stack, then
+    // print some of it back
+    let mut tape = b"'a".to_vec();
+    tape.extend(std::iter::repeat_n(&b":X'bX"[..], 40000).flatten());
+    tape.extend(b";X;X;");
+    check(&tape, b"");
+}